@@ -0,0 +1,117 @@
+use crate::util::{Vector2, Point2};
+use ggez::{
+    nalgebra::{Matrix4, Vector3},
+    graphics::Rect,
+};
+use rand::{thread_rng, Rng};
+
+use super::DELTA;
+
+pub const MIN_ZOOM: f32 = 0.25;
+pub const MAX_ZOOM: f32 = 4.;
+
+/// How much of the way to its target the camera moves every update
+const FOLLOW_SPEED: f32 = 0.15;
+/// Distance in pixels the screen moves at full shake
+const MAX_SHAKE: f32 = 12.;
+/// How much shake wears off per second
+const SHAKE_DECAY: f32 = 2.;
+
+/// What part of the world is shown on the screen and how
+#[derive(Debug, Clone)]
+pub struct Camera {
+    /// The point in the world in the middle of the screen
+    pos: Point2,
+    /// Where the camera is moving to when following something
+    target: Point2,
+    zoom: f32,
+    /// From 0 to 1
+    shake: f32,
+    shake_offset: Vector2,
+    width: f32,
+    height: f32,
+}
+
+impl Camera {
+    pub fn new(width: f32, height: f32) -> Self {
+        Camera {
+            pos: Point2::new(0., 0.),
+            target: Point2::new(0., 0.),
+            zoom: 1.,
+            shake: 0.,
+            shake_offset: Vector2::new(0., 0.),
+            width,
+            height,
+        }
+    }
+    /// Called when the window changes size
+    pub fn resize(&mut self, width: f32, height: f32) {
+        self.width = width;
+        self.height = height;
+    }
+    /// Centers the given point on the screen right away
+    pub fn focus_on(&mut self, p: Point2) {
+        self.pos = p;
+        self.target = p;
+    }
+    /// Makes the camera move smoothly towards the point
+    #[inline]
+    pub fn follow(&mut self, p: Point2) {
+        self.target = p;
+    }
+    /// Shakes the screen, adding to any shake already going on
+    pub fn shake(&mut self, amount: f32) {
+        self.shake = (self.shake + amount).min(1.);
+    }
+    #[inline]
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+    /// Moves the camera and wears off the shake, called every update
+    pub fn update(&mut self) {
+        self.pos += (self.target - self.pos) * FOLLOW_SPEED;
+
+        if self.shake > 0. {
+            // Squared so that small shakes are subtle
+            let strength = MAX_SHAKE * self.shake * self.shake;
+            let mut rng = thread_rng();
+            self.shake_offset = strength * Vector2::new(rng.gen_range(-1., 1.), rng.gen_range(-1., 1.));
+            self.shake = (self.shake - SHAKE_DECAY * DELTA).max(0.);
+        } else {
+            self.shake_offset = Vector2::new(0., 0.);
+        }
+    }
+    #[inline]
+    fn center(&self) -> Point2 {
+        self.pos + self.shake_offset
+    }
+    #[inline]
+    fn screen_center(&self) -> Vector2 {
+        0.5 * Vector2::new(self.width, self.height)
+    }
+    /// The transformation from the world to the screen
+    pub fn transform(&self) -> Matrix4<f32> {
+        let screen_center = self.screen_center();
+        let center = self.center();
+        Matrix4::new_translation(&Vector3::new(screen_center.x, screen_center.y, 0.))
+            * Matrix4::new_scaling(self.zoom)
+            * Matrix4::new_translation(&Vector3::new(-center.x, -center.y, 0.))
+    }
+    /// The part of the world that is on screen
+    pub fn view(&self) -> Rect {
+        let (w, h) = (self.width / self.zoom, self.height / self.zoom);
+        let center = self.center();
+        Rect::new(center.x - w / 2., center.y - h / 2., w, h)
+    }
+    /// Where a point on the screen is in the world
+    pub fn to_world(&self, p: Point2) -> Point2 {
+        self.center() + (p.coords - self.screen_center()) / self.zoom
+    }
+    /// Where a point in the world is on the screen
+    pub fn to_screen(&self, p: Point2) -> Point2 {
+        Point2::from(self.screen_center()) + (p - self.center()) * self.zoom
+    }
+}
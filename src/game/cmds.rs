@@ -1,11 +1,14 @@
 use std::collections::HashMap;
-use super::{Console, State, GameState, Command, CommandError, Content, StateSwitch};
+use super::{Console, State, GameState, Command, LevelCommand, CommandError, Content, StateSwitch, reload};
 
 use crate::{
     util::dbg_strs,
+    io::res,
     obj::{health::Health},
+    game::{world::{Anchor, Objective, Prefab}, score::Leaderboards, validate},
 };
 use ggez::Context;
+use clipboard::{ClipboardContext, ClipboardProvider};
 
 macro_rules! commands {
     ($console:ident, $ctx:ident, $state:ident, $gs:ident, $args:ident, $(
@@ -91,6 +94,175 @@ pub(super) fn commands() -> HashMap<String, Command> {
         } else {
             Err(NoCampaign)
         }},
+        "obj", "objectives" => {
+            let level = gs.get_level().ok_or(NoLevel)?;
+            let mut objectives = level.objectives.clone();
+            let mut added = None;
+            match args.get(1).copied() {
+                None | Some("") => {
+                    if level.objectives.is_empty() {
+                        info!("No objectives. Implicit ones are used:");
+                    }
+                    for (i, obj) in level.active_objectives().into_iter().enumerate() {
+                        info!("{}: {}", i, obj);
+                    }
+                    return Ok(());
+                }
+                Some("clear") => objectives.clear(),
+                Some("rm") => {
+                    let i: usize = args.get(2).and_then(|i| i.parse().ok()).ok_or(InvalidArg)?;
+                    if i >= objectives.len() {
+                        return Err(InvalidArg);
+                    }
+                    objectives.remove(i);
+                }
+                Some(_) => {
+                    let obj = Objective::parse(&args[1..]).ok_or(InvalidArg)?;
+                    if let Objective::Kill(id) = obj {
+                        if id >= level.enemies.len() {
+                            return Err(NoSuchEnemy);
+                        }
+                    }
+                    objectives.push(obj);
+                    added = Some(obj);
+                }
+            }
+            let from = level.objectives.clone();
+            if !gs.execute(LevelCommand::SetObjectives{from, to: objectives}) {
+                return Err(NoLevel);
+            }
+            if let Some(obj) = added {
+                info!("Added objective: {}", obj);
+            }
+            Ok(())
+        },
+        "resize" => {
+            let level = gs.get_level().ok_or(NoLevel)?;
+            let width = args.get(1).and_then(|w| w.parse().ok()).filter(|&w| w > 0).ok_or(InvalidArg)?;
+            let height = args.get(2).and_then(|h| h.parse().ok()).filter(|&h| h > 0).ok_or(InvalidArg)?;
+            let anchor = match args.get(3) {
+                Some(a) => Anchor::parse(a).ok_or(InvalidArg)?,
+                None => Anchor::TopLeft,
+            };
+            let from = Box::new(level.clone());
+            let mut to = from.clone();
+            to.resize(width, height, anchor);
+            if !gs.execute(LevelCommand::ReplaceLevel{from, to}) {
+                return Err(NoLevel);
+            }
+            info!("Resized level to {}x{}", width, height);
+            Ok(())
+        },
+        "ambient" => {
+            let from = gs.get_level().ok_or(NoLevel)?.ambient;
+            match args.get(1).copied() {
+                None | Some("") => info!("Ambient light is {}", from),
+                Some(a) => {
+                    let to = a.parse().ok().filter(|a| (0. ..=1.).contains(a)).ok_or(InvalidArg)?;
+                    if !gs.execute(LevelCommand::SetAmbient{from, to}) {
+                        return Err(NoLevel);
+                    }
+                    info!("Set ambient light to {}", to);
+                }
+            }
+            Ok(())
+        },
+        "reload" => {
+            let files = match args.get(1).copied() {
+                None | Some("") => state.watcher.changes(),
+                Some("all") => {
+                    state.watcher.changes();
+                    state.watcher.files()
+                }
+                Some("auto") => {
+                    state.watcher.auto = !state.watcher.auto;
+                    info!("Automatic reloading {}", if state.watcher.auto { "on" } else { "off" });
+                    return Ok(());
+                }
+                Some(_) => return Err(InvalidArg),
+            };
+            reload(ctx, state, gs, &files).map_err(|e| {
+                error!("{}", e);
+                NoReload
+            })?;
+            info!("Reloaded {} files", files.len());
+            Ok(())
+        },
+        "validate" => {
+            let problems = match args.get(1) {
+                Some(path) => validate::validate_path(path),
+                None => validate::validate_level(gs.get_level().ok_or(NoLevel)?),
+            };
+            for problem in &problems {
+                warn!("{}", problem);
+            }
+            info!("{} problems found", problems.len());
+            Ok(())
+        },
+        "lb", "leaderboard" => {
+            let boards = Leaderboards::load(ctx).map_err(|_| NoLeaderboards)?;
+            if args.get(1) == Some(&"all") {
+                for (hash, runs) in boards.levels() {
+                    info!("{}: {} runs", hash, runs);
+                }
+            } else {
+                let level = gs.get_level().ok_or(NoLevel)?;
+                let runs = boards.get(level.content_hash());
+                if runs.is_empty() {
+                    info!("No runs of this level");
+                }
+                for (i, run) in runs.iter().enumerate() {
+                    info!("{:>2}. {}  spotted {} alerts {} backstabs {}", i + 1, run, run.times_spotted, run.alerts, run.backstabs);
+                }
+            }
+            Ok(())
+        },
+        "lbclear" => {
+            let mut boards = Leaderboards::load(ctx).map_err(|_| NoLeaderboards)?;
+            if args.get(1) == Some(&"all") {
+                boards.clear_all();
+                info!("Cleared all leaderboards");
+            } else {
+                let level = gs.get_level().ok_or(NoLevel)?;
+                if boards.clear(level.content_hash()) {
+                    info!("Cleared leaderboard of this level");
+                }
+            }
+            boards.save(ctx).map_err(|_| NoLeaderboards)
+        },
+        "prefabs" => {
+            for file in res::list("prefabs") {
+                if let Some(name) = file.strip_suffix(".toml") {
+                    info!("{}", name);
+                }
+            }
+            Ok(())
+        },
+        "prefab" => {
+            // Put the prefab on the clipboard so it can be pasted in the editor
+            let name = args.get(1).filter(|n| Prefab::is_valid_name(n)).ok_or(InvalidArg)?;
+            let prefab = Prefab::load(name).map_err(|e| {
+                error!("{}", e);
+                NoSuchPrefab
+            })?;
+            ClipboardContext::new().and_then(|mut cc| cc.set_contents(prefab.to_text())).map_err(|_| NoClipboard)?;
+            info!("Copied prefab {}. Paste it with Ctrl+V", name);
+            Ok(())
+        },
+        "prefabsave" => {
+            let name = args.get(1).filter(|n| Prefab::is_valid_name(n)).ok_or(InvalidArg)?;
+            let text = ClipboardContext::new().and_then(|mut cc| cc.get_contents()).map_err(|_| NoClipboard)?;
+            let prefab = Prefab::from_text(&text).map_err(|e| {
+                error!("{}", e);
+                NoSuchPrefab
+            })?;
+            prefab.save(name).map_err(|e| {
+                error!("Couldn't save prefab: {}", e);
+                InvalidArg
+            })?;
+            info!("Saved prefab {}", name);
+            Ok(())
+        },
         "hello" => {
             info!("Hello!");
             Ok(())
@@ -0,0 +1,633 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use ggez::{
+    Context,
+    input::{keyboard, mouse},
+};
+
+use crate::util::Vector2;
+use super::event::{Event, KeyCode, KeyMods, MouseButton, Button, Axis};
+
+/// Something the player can do that has inputs bound to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Action {
+    /// Moving left and right, an axis
+    MoveX,
+    /// Moving up and down, an axis
+    MoveY,
+    Attack,
+    Sneak,
+    Use,
+    Reload,
+    Drop,
+    /// Picks the highlighted or first choice in menus
+    Confirm,
+    /// Leaves menus
+    Back,
+    /// Shows the map over the whole screen
+    Map,
+    Undo,
+    Redo,
+    Save,
+    /// Loads the level file again, throwing away unsaved changes
+    Revert,
+    Copy,
+    Cut,
+    Paste,
+    /// Plays the level being edited
+    Playtest,
+    /// Searches the materials by name
+    Search,
+    SelectTool,
+    BrushTool,
+    RectTool,
+    HollowRectTool,
+    LineTool,
+    FillTool,
+    Eraser,
+    /// Paints on the layer, or shows and hides it while holding Shift
+    FloorLayer,
+    WallLayer,
+    OverlayLayer,
+    /// Shows the enemies' visibility cones
+    Cones,
+    SnapToGrid,
+    /// Shows how the level is lit
+    Lighting,
+    Darker,
+    Lighter,
+    /// Makes the selected enemies targets to be killed, or not
+    Target,
+    Delete,
+    RotateLeft,
+    RotateRight,
+    /// Mirrors the selection horizontally, or vertically while holding Shift
+    Mirror,
+    /// Puts where the player starts under the mouse
+    SetStart,
+    /// Moves the selection a pixel, or a tile with Shift, aligns it with Alt or resizes the level with Ctrl, an axis
+    NudgeX,
+    /// Like `NudgeX` up and down
+    NudgeY,
+}
+
+impl Action {
+    /// The actions of the game, which can be rebound in the options
+    pub const GAME: [Action; 10] = [
+        Action::MoveX,
+        Action::MoveY,
+        Action::Attack,
+        Action::Sneak,
+        Action::Use,
+        Action::Reload,
+        Action::Drop,
+        Action::Confirm,
+        Action::Back,
+        Action::Map,
+    ];
+    /// The actions of the editor, which are only rebound in the settings file
+    pub const EDITOR: [Action; 32] = [
+        Action::Undo,
+        Action::Redo,
+        Action::Save,
+        Action::Revert,
+        Action::Copy,
+        Action::Cut,
+        Action::Paste,
+        Action::Playtest,
+        Action::Search,
+        Action::SelectTool,
+        Action::BrushTool,
+        Action::RectTool,
+        Action::HollowRectTool,
+        Action::LineTool,
+        Action::FillTool,
+        Action::Eraser,
+        Action::FloorLayer,
+        Action::WallLayer,
+        Action::OverlayLayer,
+        Action::Cones,
+        Action::SnapToGrid,
+        Action::Lighting,
+        Action::Darker,
+        Action::Lighter,
+        Action::Target,
+        Action::Delete,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::Mirror,
+        Action::SetStart,
+        Action::NudgeX,
+        Action::NudgeY,
+    ];
+    /// The name used in the settings file
+    pub fn name(self) -> &'static str {
+        use self::Action::*;
+        match self {
+            MoveX => "move_x",
+            MoveY => "move_y",
+            Attack => "attack",
+            Sneak => "sneak",
+            Use => "use",
+            Reload => "reload",
+            Drop => "drop",
+            Confirm => "confirm",
+            Back => "back",
+            Map => "map",
+            Undo => "undo",
+            Redo => "redo",
+            Save => "save",
+            Revert => "revert",
+            Copy => "copy",
+            Cut => "cut",
+            Paste => "paste",
+            Playtest => "playtest",
+            Search => "search",
+            SelectTool => "select_tool",
+            BrushTool => "brush_tool",
+            RectTool => "rect_tool",
+            HollowRectTool => "hollow_rect_tool",
+            LineTool => "line_tool",
+            FillTool => "fill_tool",
+            Eraser => "eraser",
+            FloorLayer => "floor_layer",
+            WallLayer => "wall_layer",
+            OverlayLayer => "overlay_layer",
+            Cones => "cones",
+            SnapToGrid => "snap_to_grid",
+            Lighting => "lighting",
+            Darker => "darker",
+            Lighter => "lighter",
+            Target => "target",
+            Delete => "delete",
+            RotateLeft => "rotate_left",
+            RotateRight => "rotate_right",
+            Mirror => "mirror",
+            SetStart => "set_start",
+            NudgeX => "nudge_x",
+            NudgeY => "nudge_y",
+        }
+    }
+    fn parse(s: &str) -> Option<Self> {
+        Self::GAME.iter().chain(&Self::EDITOR).copied().find(|c| c.name() == s)
+    }
+    /// Whether the action goes from -1 to 1 instead of being pressed, bound to pairs of inputs
+    #[inline]
+    pub fn is_axis(self) -> bool {
+        matches!(self, Action::MoveX | Action::MoveY | Action::NudgeX | Action::NudgeY)
+    }
+}
+
+/// A key, key with modifiers, mouse button or gamepad button
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Key(KeyCode),
+    /// A key pressed while holding modifier keys, like `Ctrl+Z`
+    Chord(KeyMods, KeyCode),
+    Mouse(MouseButton),
+    Pad(Button),
+}
+
+/// The keys that can be bound, found by their names
+const KEYS: [KeyCode; 84] = {
+    use self::KeyCode::*;
+    [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+        Up, Down, Left, Right,
+        Space, Return, Escape, Back, Delete, Insert, Home, End, PageUp, PageDown,
+        LShift, RShift, LControl, RControl, LAlt, RAlt,
+        Comma, Period, Slash, Backslash, Semicolon, Apostrophe, Minus, Equals, LBracket, RBracket,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        F1, F2, F3, F4, F5, F6, F7, F8,
+    ]
+};
+
+/// The modifier keys of chords with their names, in the order they're written
+const MODIFIERS: [(KeyMods, &str); 4] = [
+    (KeyMods::CTRL, "Ctrl"),
+    (KeyMods::SHIFT, "Shift"),
+    (KeyMods::ALT, "Alt"),
+    (KeyMods::LOGO, "Logo"),
+];
+
+/// The gamepad buttons that can be bound, found by their names after "Pad"
+const PAD_BUTTONS: [Button; 19] = {
+    use self::Button::*;
+    [
+        South, East, North, West, C, Z,
+        LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+        Select, Start, Mode, LeftThumb, RightThumb,
+        DPadUp, DPadDown, DPadLeft, DPadRight,
+    ]
+};
+
+#[inline]
+fn parse_key(s: &str) -> Option<KeyCode> {
+    KEYS.iter().copied().find(|k| format!("{:?}", k) == s)
+}
+
+#[inline]
+fn is_modifier(key: KeyCode) -> bool {
+    use self::KeyCode::*;
+    matches!(key, LShift | RShift | LControl | RControl | LAlt | RAlt | LWin | RWin)
+}
+
+impl Input {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "MouseLeft" => Some(Input::Mouse(MouseButton::Left)),
+            "MouseRight" => Some(Input::Mouse(MouseButton::Right)),
+            "MouseMiddle" => Some(Input::Mouse(MouseButton::Middle)),
+            _ => if let Some(btn) = s.strip_prefix("Pad") {
+                PAD_BUTTONS.iter().copied().find(|b| format!("{:?}", b) == btn).map(Input::Pad)
+            } else if s.contains('+') {
+                let mut parts: Vec<_> = s.split('+').collect();
+                let key = parse_key(parts.pop()?)?;
+                let mods = parts.into_iter().try_fold(KeyMods::NONE, |mods, name| {
+                    MODIFIERS.iter().find(|&&(_, n)| n == name).map(|&(m, _)| mods | m)
+                })?;
+                Some(Input::Chord(mods, key))
+            } else {
+                parse_key(s).map(Input::Key)
+            }
+        }
+    }
+    /// The input of an event if it can be bound, with the modifier keys held making it a chord
+    pub fn from_event(event: &Event, mods: KeyMods) -> Option<Self> {
+        match event {
+            Event::Key(k) if KEYS.contains(k) => if mods == KeyMods::NONE || is_modifier(*k) {
+                Some(Input::Key(*k))
+            } else {
+                Some(Input::Chord(mods, *k))
+            },
+            Event::Mouse(b @ MouseButton::Left) | Event::Mouse(b @ MouseButton::Right) | Event::Mouse(b @ MouseButton::Middle) => Some(Input::Mouse(*b)),
+            Event::Pad(b) if PAD_BUTTONS.contains(b) => Some(Input::Pad(*b)),
+            _ => None,
+        }
+    }
+    /// Whether the event is of this input, with all modifier keys of a chord held
+    fn matches(self, event: &Event, mods: KeyMods) -> bool {
+        match (self, event) {
+            (Input::Key(k), Event::Key(e)) => k == *e,
+            (Input::Chord(m, k), Event::Key(e)) => k == *e && mods.contains(m),
+            (Input::Mouse(b), Event::Mouse(e)) => b == *e,
+            (Input::Pad(b), Event::Pad(e)) => b == *e,
+            _ => false,
+        }
+    }
+    fn is_down(self, ctx: &Context, pad: &Gamepad) -> bool {
+        match self {
+            Input::Key(k) => keyboard::is_key_pressed(ctx, k),
+            Input::Chord(m, k) => keyboard::is_key_pressed(ctx, k) && keyboard::active_mods(ctx).contains(m),
+            Input::Mouse(b) => mouse::button_pressed(ctx, b),
+            Input::Pad(b) => pad.is_down(b),
+        }
+    }
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::Key(k) => write!(f, "{:?}", k),
+            Input::Chord(m, k) => {
+                for &(_, name) in MODIFIERS.iter().filter(|&&(mm, _)| m.contains(mm)) {
+                    write!(f, "{}+", name)?;
+                }
+                write!(f, "{:?}", k)
+            }
+            Input::Mouse(b) => write!(f, "Mouse{:?}", b),
+            Input::Pad(b) => write!(f, "Pad{:?}", b),
+        }
+    }
+}
+
+/// What is bound to an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// An input that does the action
+    Button(Input),
+    /// Inputs moving an axis action to its negative and positive ends, written like `A/D`
+    Axis(Input, Input),
+}
+
+impl Binding {
+    fn parse(s: &str, axis: bool) -> Option<Self> {
+        if axis {
+            let mut ends = s.split('/');
+            let (neg, pos) = (ends.next()?, ends.next()?);
+            if ends.next().is_some() {
+                return None
+            }
+            Some(Binding::Axis(Input::parse(neg)?, Input::parse(pos)?))
+        } else {
+            Input::parse(s).map(Binding::Button)
+        }
+    }
+    /// -1 or 1 for the end of an axis the event is of, 1 for a button, 0 if it isn't of this
+    fn event_value(self, event: &Event, mods: KeyMods) -> f32 {
+        match self {
+            Binding::Button(i) => <f32>::from(i.matches(event, mods) as i8),
+            Binding::Axis(neg, pos) => <f32>::from(pos.matches(event, mods) as i8 - neg.matches(event, mods) as i8),
+        }
+    }
+    fn inputs(&self) -> impl Iterator<Item=Input> {
+        match *self {
+            Binding::Button(i) => vec![i],
+            Binding::Axis(neg, pos) => vec![neg, pos],
+        }.into_iter()
+    }
+}
+
+impl Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Button(i) => i.fmt(f),
+            Binding::Axis(neg, pos) => write!(f, "{}/{}", neg, pos),
+        }
+    }
+}
+
+/// The inputs bound to each action
+///
+/// Saved as a table of action names to lists of bindings, like `attack = ["MouseLeft", "Space"]`
+/// or `move_x = ["A/D", "Left/Right"]` for axes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "BTreeMap<String, Vec<String>>", into = "BTreeMap<String, Vec<String>>")]
+pub struct Controls {
+    bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        use self::Input::{Key, Chord, Mouse, Pad};
+        use self::Binding::{Button as Btn, Axis};
+        use self::KeyCode::*;
+        let bindings = vec![
+            (Action::MoveX, vec![Axis(Key(A), Key(D)), Axis(Key(Left), Key(Right)), Axis(Pad(Button::DPadLeft), Pad(Button::DPadRight))]),
+            (Action::MoveY, vec![Axis(Key(W), Key(S)), Axis(Key(Up), Key(Down)), Axis(Pad(Button::DPadUp), Pad(Button::DPadDown))]),
+            (Action::Attack, vec![Btn(Mouse(MouseButton::Left)), Btn(Key(Space)), Btn(Pad(Button::RightTrigger2))]),
+            (Action::Sneak, vec![Btn(Key(LShift)), Btn(Key(RShift)), Btn(Pad(Button::LeftTrigger2))]),
+            (Action::Use, vec![Btn(Key(F)), Btn(Pad(Button::South))]),
+            (Action::Reload, vec![Btn(Key(R)), Btn(Pad(Button::West))]),
+            (Action::Drop, vec![Btn(Key(G)), Btn(Pad(Button::North))]),
+            (Action::Confirm, vec![Btn(Key(Return)), Btn(Pad(Button::South)), Btn(Pad(Button::Start))]),
+            (Action::Back, vec![Btn(Key(Escape)), Btn(Pad(Button::East))]),
+            (Action::Map, vec![Btn(Key(M)), Btn(Pad(Button::Select))]),
+            (Action::Undo, vec![Btn(Chord(KeyMods::CTRL, Z))]),
+            (Action::Redo, vec![Btn(Chord(KeyMods::CTRL, Y))]),
+            (Action::Save, vec![Btn(Key(Z))]),
+            (Action::Revert, vec![Btn(Key(X))]),
+            (Action::Copy, vec![Btn(Chord(KeyMods::CTRL, C))]),
+            (Action::Cut, vec![Btn(Chord(KeyMods::CTRL, X))]),
+            (Action::Paste, vec![Btn(Chord(KeyMods::CTRL, V))]),
+            (Action::Playtest, vec![Btn(Key(P))]),
+            (Action::Search, vec![Btn(Key(Slash))]),
+            (Action::SelectTool, vec![Btn(Key(T))]),
+            (Action::BrushTool, vec![Btn(Key(B))]),
+            (Action::RectTool, vec![Btn(Key(R))]),
+            (Action::HollowRectTool, vec![Btn(Key(H))]),
+            (Action::LineTool, vec![Btn(Key(L))]),
+            (Action::FillTool, vec![Btn(Key(F))]),
+            (Action::Eraser, vec![Btn(Key(E))]),
+            (Action::FloorLayer, vec![Btn(Key(Key1))]),
+            (Action::WallLayer, vec![Btn(Key(Key2))]),
+            (Action::OverlayLayer, vec![Btn(Key(Key3))]),
+            (Action::Cones, vec![Btn(Key(C))]),
+            (Action::SnapToGrid, vec![Btn(Key(G))]),
+            (Action::Lighting, vec![Btn(Key(I))]),
+            (Action::Darker, vec![Btn(Key(LBracket))]),
+            (Action::Lighter, vec![Btn(Key(RBracket))]),
+            (Action::Target, vec![Btn(Key(K))]),
+            (Action::Delete, vec![Btn(Key(Delete)), Btn(Key(Back))]),
+            (Action::RotateLeft, vec![Btn(Key(Comma))]),
+            (Action::RotateRight, vec![Btn(Key(Period))]),
+            (Action::Mirror, vec![Btn(Key(M))]),
+            (Action::SetStart, vec![Btn(Key(Q)), Btn(Mouse(MouseButton::Middle))]),
+            (Action::NudgeX, vec![Axis(Key(Left), Key(Right))]),
+            (Action::NudgeY, vec![Axis(Key(Up), Key(Down))]),
+        ];
+        Controls {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl From<BTreeMap<String, Vec<String>>> for Controls {
+    /// Controls missing from the file keep their default bindings
+    fn from(raw: BTreeMap<String, Vec<String>>) -> Self {
+        let mut controls = Controls::default();
+        for (name, bindings) in raw {
+            match Action::parse(&name) {
+                Some(action) => {
+                    let bindings = bindings.iter().filter_map(|b| {
+                        let binding = Binding::parse(b, action.is_axis());
+                        if binding.is_none() {
+                            warn!("Unknown input {} bound to {}", b, name);
+                        }
+                        binding
+                    }).collect();
+                    controls.bindings.insert(action, bindings);
+                }
+                None => warn!("Unknown action {}", name),
+            }
+        }
+        controls
+    }
+}
+
+impl From<Controls> for BTreeMap<String, Vec<String>> {
+    fn from(controls: Controls) -> Self {
+        controls.bindings.into_iter()
+            .map(|(c, bindings)| (c.name().to_owned(), bindings.iter().map(Binding::to_string).collect()))
+            .collect()
+    }
+}
+
+impl Controls {
+    #[inline]
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map(|v| &**v).unwrap_or(&[])
+    }
+    /// Makes the binding the only one of the action
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        self.bindings.insert(action, vec![binding]);
+    }
+    /// Whether the event is of an input bound to the action, or to either end of it for axes
+    pub fn matches(&self, ctx: &Context, action: Action, event: &Event) -> bool {
+        let mods = keyboard::active_mods(ctx);
+        self.get(action).iter().any(|b| b.event_value(event, mods) != 0.)
+    }
+    /// Which end of the axis action the event is of, 0 if neither
+    pub fn axis_event(&self, ctx: &Context, action: Action, event: &Event) -> f32 {
+        let mods = keyboard::active_mods(ctx);
+        self.get(action).iter().map(|b| b.event_value(event, mods)).find(|&v| v != 0.).unwrap_or(0.)
+    }
+    /// Whether any input of the action is held down
+    pub fn is_down(&self, ctx: &Context, pad: &Gamepad, action: Action) -> bool {
+        self.get(action).iter().flat_map(Binding::inputs).any(|i| i.is_down(ctx, pad))
+    }
+    /// How far the axis action is pushed by the inputs held down, leaving out those also bound to `except`
+    pub fn axis_except(&self, ctx: &Context, pad: &Gamepad, action: Action, except: Option<Action>) -> f32 {
+        let excepted: Vec<Input> = except.map_or(Vec::new(), |e| self.get(e).iter().flat_map(Binding::inputs).collect());
+        let held = |i: Input| <f32>::from((!excepted.contains(&i) && i.is_down(ctx, pad)) as i8);
+
+        self.get(action).iter()
+            .map(|b| match *b {
+                Binding::Axis(neg, pos) => held(pos) - held(neg),
+                Binding::Button(_) => 0.,
+            })
+            .sum::<f32>()
+            .clamp(-1., 1.)
+    }
+    /// How far the axis action is pushed by the inputs held down, from -1 to 1
+    #[inline]
+    pub fn axis(&self, ctx: &Context, pad: &Gamepad, action: Action) -> f32 {
+        self.axis_except(ctx, pad, action, None)
+    }
+    /// Movement from the left stick, or from the movement axes if it isn't used
+    ///
+    /// The axes go from -1 (left, up) to 1 (right, down).
+    pub fn movement(&self, ctx: &Context, pad: &Gamepad) -> Vector2 {
+        let stick = pad.movement();
+        if stick != Vector2::new(0., 0.) {
+            return stick
+        }
+        Vector2::new(self.axis(ctx, pad, Action::MoveX), self.axis(ctx, pad, Action::MoveY))
+    }
+}
+
+/// Sticks within this of the middle count as not being moved
+const DEAD_ZONE: f32 = 0.2;
+
+/// The state of the gamepads, kept from their events
+#[derive(Debug, Clone)]
+pub struct Gamepad {
+    held: Vec<Button>,
+    left_stick: Vector2,
+    right_stick: Vector2,
+    /// Where the right stick was last pointed, if it's been used since the mouse moved
+    aim: Option<Vector2>,
+}
+
+impl Default for Gamepad {
+    fn default() -> Self {
+        Gamepad {
+            held: Vec::new(),
+            left_stick: Vector2::new(0., 0.),
+            right_stick: Vector2::new(0., 0.),
+            aim: None,
+        }
+    }
+}
+
+impl Gamepad {
+    pub fn button_down(&mut self, btn: Button) {
+        if !self.held.contains(&btn) {
+            self.held.push(btn);
+        }
+    }
+    pub fn button_up(&mut self, btn: Button) {
+        self.held.retain(|&b| b != btn);
+    }
+    #[inline]
+    pub fn is_down(&self, btn: Button) -> bool {
+        self.held.contains(&btn)
+    }
+    pub fn axis(&mut self, axis: Axis, value: f32) {
+        // Up is positive on gamepads but negative on the screen
+        match axis {
+            Axis::LeftStickX => self.left_stick.x = value,
+            Axis::LeftStickY => self.left_stick.y = -value,
+            Axis::RightStickX => self.right_stick.x = value,
+            Axis::RightStickY => self.right_stick.y = -value,
+            _ => return,
+        }
+        if self.right_stick.norm() > DEAD_ZONE {
+            self.aim = Some(self.right_stick.normalize());
+        }
+    }
+    /// The left stick's position, which is zero inside the dead zone
+    pub fn movement(&self) -> Vector2 {
+        if self.left_stick.norm() > DEAD_ZONE {
+            self.left_stick
+        } else {
+            Vector2::new(0., 0.)
+        }
+    }
+    /// The direction to aim in, if the right stick is used instead of the mouse
+    #[inline]
+    pub fn aim(&self) -> Option<Vector2> {
+        self.aim
+    }
+    /// Makes the mouse be used for aiming again
+    #[inline]
+    pub fn mouse_moved(&mut self) {
+        self.aim = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(pairs: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.iter().map(|s| s.to_string()).collect())).collect()
+    }
+
+    #[test]
+    fn inputs_round_trip() {
+        for s in &["W", "Escape", "LBracket", "MouseLeft", "PadDPadUp", "Ctrl+Z", "Ctrl+Shift+Up"] {
+            assert_eq!(Input::parse(s).unwrap().to_string(), *s);
+        }
+        assert_eq!(Input::parse("Ctrl+Z"), Some(Input::Chord(KeyMods::CTRL, KeyCode::Z)));
+        assert_eq!(Input::parse("Hyper+Z"), None);
+        assert_eq!(Input::parse("Ctrl+"), None);
+        assert_eq!(Input::parse("Tab"), None);
+    }
+
+    #[test]
+    fn axes_are_pairs() {
+        assert_eq!(Binding::parse("A/D", true), Some(Binding::Axis(Input::Key(KeyCode::A), Input::Key(KeyCode::D))));
+        assert_eq!(Binding::parse("A", true), None);
+        assert_eq!(Binding::parse("A/D/W", true), None);
+        assert_eq!(Binding::parse("A/D", false), None);
+        assert_eq!(Binding::Axis(Input::Key(KeyCode::Left), Input::Key(KeyCode::Right)).to_string(), "Left/Right");
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let controls = Controls::default();
+        let parsed = Controls::from(BTreeMap::from(controls.clone()));
+        assert_eq!(parsed, controls);
+
+        let text = toml::to_string(&controls).unwrap();
+        assert_eq!(toml::from_str::<Controls>(&text).unwrap(), controls);
+    }
+
+    #[test]
+    fn missing_and_unknown_bindings() {
+        let controls = Controls::from(raw(&[
+            ("attack", &["Space", "Nonsense"]),
+            ("fly", &["F"]),
+        ]));
+        assert_eq!(controls.get(Action::Attack), &[Binding::Button(Input::Key(KeyCode::Space))]);
+        assert_eq!(controls.get(Action::Use), Controls::default().get(Action::Use));
+    }
+
+    #[test]
+    fn chords_need_their_modifiers() {
+        let undo = Binding::Button(Input::Chord(KeyMods::CTRL, KeyCode::Z));
+        let save = Binding::Button(Input::Key(KeyCode::Z));
+        let z = Event::Key(KeyCode::Z);
+        assert_eq!(undo.event_value(&z, KeyMods::NONE), 0.);
+        assert_eq!(undo.event_value(&z, KeyMods::CTRL | KeyMods::SHIFT), 1.);
+        assert_eq!(save.event_value(&z, KeyMods::CTRL), 1.);
+
+        let axis = Binding::Axis(Input::Key(KeyCode::A), Input::Key(KeyCode::D));
+        assert_eq!(axis.event_value(&Event::Key(KeyCode::A), KeyMods::NONE), -1.);
+        assert_eq!(axis.event_value(&Event::Key(KeyCode::D), KeyMods::NONE), 1.);
+        assert_eq!(axis.event_value(&Event::Key(KeyCode::W), KeyMods::NONE), 0.);
+    }
+}
@@ -0,0 +1,68 @@
+use ggez::{Context, GameResult, GameError, filesystem};
+
+use crate::{
+    util::{Point2, TRANS},
+    io::tex::Assets,
+    obj::Object,
+};
+
+/// A recording of where the player was on every tick of a run
+///
+/// The fastest completed run of a level is saved so it can be raced on later attempts.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Ghost {
+    frames: Vec<(f32, f32, f32)>,
+}
+
+#[inline]
+fn path(level_hash: u64) -> String {
+    format!("/ghosts/{:016x}.ghost", level_hash)
+}
+
+impl Ghost {
+    /// Loads the ghost of the level with the given content hash if there is one
+    pub fn load(ctx: &mut Context, level_hash: u64) -> GameResult<Option<Self>> {
+        let path = path(level_hash);
+        if !filesystem::exists(ctx, &path) {
+            return Ok(None)
+        }
+        let file = filesystem::open(ctx, path)?;
+
+        bincode::deserialize_from(file)
+            .map(Some)
+            .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))
+    }
+    pub fn save(&self, ctx: &mut Context, level_hash: u64) -> GameResult<()> {
+        filesystem::create_dir(ctx, "/ghosts")?;
+        let file = filesystem::create(ctx, path(level_hash))?;
+
+        bincode::serialize_into(file, self)
+            .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))
+    }
+    /// Records the object's position and rotation for the current tick
+    #[inline]
+    pub fn record(&mut self, obj: &Object) {
+        self.frames.push((obj.pos.x, obj.pos.y, obj.rot));
+    }
+    /// Length of the recording in ticks
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+    /// Where the ghost is at the given tick, staying at the end once the recording is over
+    pub fn at(&self, tick: usize) -> Option<Object> {
+        self.frames.get(tick).or_else(|| self.frames.last())
+            .map(|&(x, y, rot)| Object::with_rot(Point2::new(x, y), rot))
+    }
+    pub fn draw(&self, tick: usize, ctx: &mut Context, assets: &Assets) -> GameResult<()> {
+        if let Some(obj) = self.at(tick) {
+            let img = assets.get_img(ctx, "common/player");
+            obj.draw(ctx, &img, TRANS)?;
+        }
+        Ok(())
+    }
+}
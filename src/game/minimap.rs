@@ -0,0 +1,128 @@
+use crate::util::{Vector2, Point2};
+use ggez::{
+    Context, GameResult,
+    graphics::{self, Image, Color, Rect, DrawMode, DrawParam, FilterMode, Mesh, MeshBuilder, WHITE},
+};
+
+use super::{State, world::{Grid, Palette, Fog}};
+
+/// Longest side of the minimap in the corner
+const CORNER_SIZE: f32 = 180.;
+/// Space around the minimap and the full map
+const MARGIN: f32 = 8.;
+
+const FLOOR: [u8; 4] = [140, 140, 140, 255];
+const WALL: [u8; 4] = [40, 40, 40, 255];
+const BACKGROUND: Color = Color{r: 0., g: 0., b: 0., a: 0.5};
+
+/// A map of a level with one pixel per tile, shown in the corner or over the whole screen
+pub struct Minimap {
+    image: Image,
+    width: u16,
+    height: u16,
+    /// Whether it covers the screen instead of the corner
+    pub full: bool,
+}
+
+/// Tiles hidden by the fog of war are left out
+fn build_image(ctx: &mut Context, pal: &Palette, floor: &Grid, grid: &Grid, fog: Option<&Fog>) -> GameResult<Image> {
+    let (width, height) = (grid.width(), grid.height());
+    let mut rgba = Vec::with_capacity(4 * width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = if fog.is_some_and(|fog| !fog.is_explored(x, y)) {
+                [0; 4]
+            } else if grid.is_solid(pal, x, y) {
+                WALL
+            } else if grid.get(x, y) != Some(Grid::EMPTY) || floor.get(x, y).is_some_and(|m| m != Grid::EMPTY) {
+                FLOOR
+            } else {
+                [0; 4]
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    let mut image = Image::from_rgba8(ctx, width, height, &rgba)?;
+    image.set_filter(FilterMode::Nearest);
+    Ok(image)
+}
+
+impl Minimap {
+    pub fn new(ctx: &mut Context, pal: &Palette, floor: &Grid, grid: &Grid, fog: Option<&Fog>) -> GameResult<Self> {
+        Ok(Minimap {
+            image: build_image(ctx, pal, floor, grid, fog)?,
+            width: grid.width(),
+            height: grid.height(),
+            full: false,
+        })
+    }
+    /// Draws the tiles again after they or the fog have changed
+    pub fn refresh(&mut self, ctx: &mut Context, pal: &Palette, floor: &Grid, grid: &Grid, fog: Option<&Fog>) -> GameResult<()> {
+        self.image = build_image(ctx, pal, floor, grid, fog)?;
+        self.width = grid.width();
+        self.height = grid.height();
+        Ok(())
+    }
+    #[inline]
+    pub fn toggle(&mut self) {
+        self.full = !self.full;
+    }
+    /// Where on the screen the map is and how many pixels a tile takes up
+    fn placement(&self, s: &State) -> (Rect, f32) {
+        let (w, h) = (f32::from(self.width.max(1)), f32::from(self.height.max(1)));
+        if self.full {
+            let scale = ((s.width - 8. * MARGIN) / w).min((s.height - 8. * MARGIN) / h);
+            (Rect::new((s.width - scale * w) / 2., (s.height - scale * h) / 2., scale * w, scale * h), scale)
+        } else {
+            let scale = (CORNER_SIZE / w).min(CORNER_SIZE / h);
+            (Rect::new(s.width - scale * w - MARGIN, s.height - scale * h - MARGIN, scale * w, scale * h), scale)
+        }
+    }
+    /// Whether the point on the screen is on the map
+    pub fn contains(&self, s: &State, p: Point2) -> bool {
+        self.placement(s).0.contains(p)
+    }
+    /// The point in the world that a point on the map shows
+    pub fn to_world(&self, s: &State, p: Point2) -> Point2 {
+        let (rect, scale) = self.placement(s);
+        Point2::new((p.x - rect.x) / scale * 32., (p.y - rect.y) / scale * 32.)
+    }
+    fn to_map(rect: Rect, scale: f32, p: Point2) -> Point2 {
+        Point2::new(rect.x, rect.y) + p.coords * (scale / 32.)
+    }
+    /// Draws the map with dots of the given colours and the camera's view outlined
+    pub fn draw(&self, ctx: &mut Context, s: &State, markers: &[(Point2, Color)]) -> GameResult<()> {
+        let (rect, scale) = self.placement(s);
+
+        let background = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect::new(rect.x - MARGIN / 2., rect.y - MARGIN / 2., rect.w + MARGIN, rect.h + MARGIN), BACKGROUND)?;
+        graphics::draw(ctx, &background, DrawParam::default())?;
+
+        let param = DrawParam {
+            dest: Point2::new(rect.x, rect.y).into(),
+            scale: Vector2::new(scale, scale).into(),
+            .. Default::default()
+        };
+        graphics::draw(ctx, &self.image, param)?;
+
+        // The view clipped to the map
+        let view = s.view();
+        let top_left = Self::to_map(rect, scale, Point2::new(view.x, view.y));
+        let bottom_right = Self::to_map(rect, scale, Point2::new(view.right(), view.bottom()));
+        let (x0, y0) = (top_left.x.max(rect.x), top_left.y.max(rect.y));
+        let (x1, y1) = (bottom_right.x.min(rect.right()), bottom_right.y.min(rect.bottom()));
+        let mut mb = MeshBuilder::new();
+        if x1 > x0 && y1 > y0 {
+            mb.rectangle(DrawMode::stroke(1.), Rect::new(x0, y0, x1 - x0, y1 - y0), WHITE);
+        }
+        let radius = if self.full { 4. } else { 2. };
+        for &(p, colour) in markers {
+            mb.circle(DrawMode::fill(), Self::to_map(rect, scale, p), radius, 0.5, colour);
+        }
+        // Building an empty mesh fails
+        if x1 > x0 && y1 > y0 || !markers.is_empty() {
+            let mesh = mb.build(ctx)?;
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+        Ok(())
+    }
+}
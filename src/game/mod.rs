@@ -3,31 +3,44 @@ use std::sync::Mutex;
 use std::fmt::{self, Display};
 use std::collections::HashMap;
 use crate::{
-    util::{Vector2, Point2, RED, GREEN, BLUE},
+    util::{Point2, RED, GREEN, BLUE},
     io::{
         snd::MediaPlayer,
+        res,
+        error::ContentError,
         tex::{Assets, PosText},
+        watch::Watcher,
     },
     obj::{health::Health},
 };
 use ggez::{
-    nalgebra::Matrix4,
     Context, GameResult,
     graphics::{self, DrawMode, Rect, Mesh, Text, TextFragment, DrawParam, Color},
     timer,
     input::mouse::{self, MouseCursor},
-    event::EventHandler
+    event::{EventHandler, GamepadId},
 };
 use clipboard::{ClipboardContext, ClipboardProvider};
 use self::world::Level;
+use self::settings::Settings;
+use self::controls::Gamepad;
+use self::camera::Camera;
 use log::{Log, Metadata, Record, Level as LogLevel};
 use lazy_static::lazy_static;
 
 /// Stuff related to things in the world
 pub mod world;
 pub mod states;
-
-use self::states::menu::Menu;
+pub mod score;
+pub mod ghost;
+pub mod validate;
+pub mod settings;
+pub mod controls;
+pub mod camera;
+pub mod minimap;
+pub mod particles;
+
+use self::states::{menu::Menu, editor::history::Command as LevelCommand};
 use self::world::Statistics;
 
 pub enum StateSwitch {
@@ -40,13 +53,22 @@ pub enum StateSwitch {
     },
     Lose(Box<Statistics>),
     Win(Box<Statistics>),
+    Options,
+    /// Show the error instead of crashing
+    Error(String),
 }
 
 pub mod event {
-    pub use ggez::event::{MouseButton, KeyCode, KeyMods};
+    pub use ggez::event::{MouseButton, KeyCode, KeyMods, Button, Axis};
     pub enum Event {
         Key(KeyCode),
         Mouse(MouseButton),
+        /// A button on any gamepad
+        Pad(Button),
+        /// Vertical mouse wheel movement
+        Wheel(f32),
+        /// A character typed while the console is closed
+        Text(char),
     }
 }
 
@@ -65,6 +87,10 @@ pub trait GameState {
     fn draw_hud(&mut self, _: &State, _: &mut Context) -> GameResult<()>;
     fn event_down(&mut self, _: &mut State, _: &mut Context, _: Event) { }
     fn event_up(&mut self, _: &mut State, _: &mut Context, _: Event) { }
+    /// Called after resource files have been reloaded, named by their paths in the resource packs
+    fn reload(&mut self, _: &mut State, _: &mut Context, _: &[String]) { }
+    /// Called after the window has changed size to place the HUD again
+    fn resize(&mut self, _: &mut State, _: &mut Context) { }
 
     fn get_world(&self) -> Option<&world::World> {
         None
@@ -72,6 +98,13 @@ pub trait GameState {
     fn get_mut_world(&mut self) -> Option<&mut world::World> {
         None
     }
+    fn get_level(&self) -> Option<&world::Level> {
+        None
+    }
+    /// Applies an undoable change to the level being edited, returning whether there is one
+    fn execute(&mut self, _: LevelCommand) -> bool {
+        false
+    }
 }
 
 lazy_static! {
@@ -139,9 +172,15 @@ pub struct Console {
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum CommandError {
     NoWorld,
+    NoLevel,
     NoCampaign,
+    NoLeaderboards,
     InvalidArg,
     NoSuchLevel,
+    NoSuchPrefab,
+    NoSuchEnemy,
+    NoClipboard,
+    NoReload,
 }
 
 impl Display for CommandError {
@@ -149,9 +188,15 @@ impl Display for CommandError {
         use self::CommandError::*;
         match *self {
             NoWorld => "No world".fmt(f),
+            NoLevel => "No level".fmt(f),
+            NoLeaderboards => "Leaderboards couldn't be read or written".fmt(f),
             NoCampaign => "No campaign loaded".fmt(f),
             InvalidArg => "Invalid argument".fmt(f),
             NoSuchLevel => "No such level".fmt(f),
+            NoSuchPrefab => "No such prefab".fmt(f),
+            NoSuchEnemy => "No such enemy".fmt(f),
+            NoClipboard => "Couldn't use the clipboard".fmt(f),
+            NoReload => "Couldn't reload the files".fmt(f),
         }
     }
 }
@@ -246,9 +291,12 @@ pub struct State {
     width: f32,
     height: f32,
     mouse: Point2,
-    offset: Vector2,
+    camera: Camera,
     switch_state: Option<StateSwitch>,
     content: Content,
+    watcher: Watcher,
+    settings: Settings,
+    pad: Gamepad,
 }
 
 const DESIRED_FPS: u32 = 60;
@@ -260,7 +308,15 @@ impl Master {
     pub fn new(ctx: &mut Context, arg: &str) -> GameResult<Self> {
         // Initialise assets
         let assets = Assets::new(ctx)?;
+        // Made first so it gets what's logged while loading
+        let console = Console::new(ctx, &assets)?;
+        let settings = Settings::load(ctx).unwrap_or_else(|e| {
+            warn!("Couldn't load settings: {}", e);
+            Settings::default()
+        });
+        settings.apply_window(ctx)?;
         let mut mplayer = MediaPlayer::new();
+        mplayer.set_volumes(settings.master_volume, settings.music_volume, settings.effects_volume);
         mplayer.register_music(ctx, "music", true)?;
         mplayer.register_music(ctx, "victory", false)?;
 
@@ -283,11 +339,14 @@ impl Master {
             width,
             height,
             mouse: Point2::new(0., 0.),
-            offset: Vector2::new(0., 0.),
+            camera: Camera::new(width, height),
+            watcher: Watcher::new(),
+            settings,
+            pad: Gamepad::default(),
         };
 
         Ok(Master {
-            console: Console::new(ctx, &state.assets)?,
+            console,
             console_status: ConsoleStatus::Closed,
             gs: Menu::new(ctx, &mut state)?,
             state,
@@ -296,15 +355,50 @@ impl Master {
 }
 
 impl State {
-    /// Sets the offset so that the given point will be centered on the screen
-    fn focus_on(&mut self, p: Point2) {
-        self.offset = -p.coords + 0.5 * Vector2::new(self.width, self.height);
+    /// The part of the world that is on screen
+    #[inline]
+    fn view(&self) -> Rect {
+        self.camera.view()
+    }
+    /// Where the mouse is in the world
+    #[inline]
+    fn mouse_world(&self) -> Point2 {
+        self.camera.to_world(self.mouse)
     }
     fn switch(&mut self, ss: StateSwitch) {
         self.switch_state = Some(ss);
     }
 }
 
+/// Loads the resource files again, named by their paths in the resource packs
+fn reload(ctx: &mut Context, s: &mut State, gs: &mut dyn GameState, files: &[String]) -> GameResult<()> {
+    let mut sprites = Vec::new();
+    for file in files {
+        let (name, ext) = match file.rfind('.') {
+            Some(i) => (&file[..i], &file[i+1..]),
+            None => continue,
+        };
+        match ext {
+            "mat" => world::forget_material(name.trim_start_matches("materials/")),
+            "light" => world::forget_light(name),
+            "png" => sprites.push(name),
+            "wav" | "ogg" => s.mplayer.reload(ctx, name.trim_start_matches("sounds/"))?,
+            _ => (),
+        }
+    }
+    s.assets.reload(ctx, &sprites)?;
+
+    // The tiles have to be redrawn with the new sprites and materials
+    if let Some(level) = gs.get_level() {
+        level.invalidate();
+    }
+    if let Some(world) = gs.get_world() {
+        world.invalidate();
+    }
+    gs.reload(s, ctx, files);
+    Ok(())
+}
+
 use std::mem;
 
 impl EventHandler for Master {
@@ -315,14 +409,30 @@ impl EventHandler for Master {
             mouse::set_cursor_type(ctx, MouseCursor::Default);
 
             use self::StateSwitch::*;
-            self.gs = match gsb {
+            let gs = match gsb {
                 PlayWith{lvl, health} => states::play::Play::new(ctx, &mut self.state, *lvl, Some((health))),
                 Play(lvl) => states::play::Play::new(ctx, &mut self.state, lvl, None),
                 Menu => states::menu::Menu::new(ctx, &mut self.state),
-                Editor(l) => states::editor::Editor::new(&self.state, l),
+                Editor(l) => states::editor::Editor::new(ctx, &self.state, l),
                 Win(stats) => states::win::Win::new(ctx, &mut self.state, *stats),
                 Lose(stats) => states::lose::Lose::new(ctx, &mut self.state, *stats),
-            }?;
+                Options => states::options::Options::new(ctx, &mut self.state),
+                Error(e) => states::error::ErrorScreen::new(ctx, &mut self.state, &e),
+            };
+            self.gs = match gs {
+                Ok(gs) => gs,
+                Err(e) => {
+                    error!("{}", e);
+                    states::error::ErrorScreen::new(ctx, &mut self.state, &e.to_string())?
+                }
+            };
+        }
+        let changed = self.state.watcher.poll();
+        if !changed.is_empty() {
+            match reload(ctx, &mut self.state, &mut *self.gs, &changed) {
+                Ok(()) => info!("Reloaded {}", changed.join(", ")),
+                Err(e) => error!("Couldn't reload: {}", e),
+            }
         }
         if self.console_status.is_open() {
             while timer::check_update_time(ctx, DESIRED_FPS) {}
@@ -345,6 +455,7 @@ impl EventHandler for Master {
             // Can in theory become slow
             while timer::check_update_time(ctx, DESIRED_FPS) {
                 self.gs.update(&mut self.state, ctx)?;
+                self.state.camera.update();
             }
             self.gs.logic(&mut self.state, ctx)
         }
@@ -355,13 +466,13 @@ impl EventHandler for Master {
         // Clear the screen first
         graphics::clear(ctx, (33, 33, 255, 255).into());
 
-        // Offset the current drawing with a translation from the `offset`
-        graphics::push_transform(ctx, Some(Matrix4::new_translation(&self.state.offset.fixed_resize(0.))));
+        // Draw the world as the camera sees it
+        graphics::push_transform(ctx, Some(self.state.camera.transform()));
         graphics::apply_transformations(ctx)?;
 
         self.gs.draw(&self.state, ctx)?;
 
-        // Pop the offset tranformation to draw the UI on the screen
+        // Pop the camera's tranformation to draw the UI on the screen
         graphics::pop_transform(ctx);
         graphics::apply_transformations(ctx)?;
 
@@ -418,6 +529,31 @@ impl EventHandler for Master {
             self.gs.event_up(&mut self.state, ctx, Event::Mouse(btn))
         }
     }
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        // Show more of the world instead of stretching it
+        if let Err(e) = graphics::set_screen_coordinates(ctx, Rect::new(0., 0., width, height)) {
+            error!("Couldn't resize: {}", e);
+        }
+        self.state.width = width;
+        self.state.height = height;
+        self.state.camera.resize(width, height);
+        self.gs.resize(&mut self.state, ctx);
+    }
+    fn gamepad_button_down_event(&mut self, ctx: &mut Context, btn: Button, _id: GamepadId) {
+        self.state.pad.button_down(btn);
+        if !self.console_status.is_open() {
+            self.gs.event_down(&mut self.state, ctx, Event::Pad(btn))
+        }
+    }
+    fn gamepad_button_up_event(&mut self, ctx: &mut Context, btn: Button, _id: GamepadId) {
+        self.state.pad.button_up(btn);
+        if !self.console_status.is_open() {
+            self.gs.event_up(&mut self.state, ctx, Event::Pad(btn))
+        }
+    }
+    fn gamepad_axis_event(&mut self, _ctx: &mut Context, axis: Axis, value: f32, _id: GamepadId) {
+        self.state.pad.axis(axis, value);
+    }
     fn text_input_event(&mut self, ctx: &mut Context, c: char) {
         if self.console_status.is_open() {
             if c.is_control() {
@@ -445,11 +581,19 @@ impl EventHandler for Master {
             } else {
                 self.console.prompt.text.fragments_mut()[1].text.push(c);
             }
+        } else {
+            self.gs.event_up(&mut self.state, ctx, Event::Text(c))
+        }
+    }
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: f32, y: f32) {
+        if !self.console_status.is_open() {
+            self.gs.event_up(&mut self.state, ctx, Event::Wheel(y))
         }
     }
     /// Handles mouse movement events
     fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32, _: f32, _: f32) {
         self.state.mouse = Point2::new(x, y);
+        self.state.pad.mouse_moved();
         if let ConsoleStatus::Open{cursor, cursor_hidden} = self.console_status {
             if y > PROMPT_Y {
                 mouse::set_cursor_type(ctx, cursor);
@@ -466,8 +610,7 @@ impl EventHandler for Master {
 }
 
 
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, Cursor};
 
 pub struct Campaign {
     pub levels: Vec<Level>,
@@ -475,14 +618,27 @@ pub struct Campaign {
 }
 
 impl Campaign {
-    pub fn load<P: AsRef<Path>>(p: P) -> GameResult<Self> {
-        let file = BufReader::new(File::open(p)?);
+    pub fn load<P: AsRef<Path>>(p: P) -> Result<Self, ContentError> {
+        let name = p.as_ref().display().to_string();
+        let file = Cursor::new(res::read_content(p).map_err(|e| ContentError::io(&*name, e))?);
 
         let mut levels = Vec::new();
 
-        for line in file.lines() {
-            let line = line?;
-            levels.push(Level::load(&line.trim())?);
+        // The levels can be in the same mod as the campaign
+        for (i, line) in file.lines().enumerate() {
+            let line = line.map_err(|e| ContentError::io(&*name, e))?;
+            let path = line.trim();
+            if path.is_empty() {
+                continue
+            }
+            let level = res::read_content(path)
+                .map_err(|e| ContentError::io(path, e))
+                .and_then(|buf| Level::load_from(Cursor::new(buf), path))
+                .map_err(|e| ContentError::CampaignLevel{file: name.clone(), line: i + 1, error: Box::new(e)})?;
+            levels.push(level);
+        }
+        if levels.is_empty() {
+            return Err(ContentError::EmptyCampaign{file: name});
         }
 
         Ok(Campaign {
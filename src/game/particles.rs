@@ -0,0 +1,167 @@
+use crate::{
+    util::{Point2, Vector2, angle_to_vec},
+    io::tex::Assets,
+};
+use ggez::{
+    Context, GameResult,
+    graphics::{self, Color, DrawParam, spritebatch::SpriteBatch},
+};
+use rand::{thread_rng, Rng};
+use std::collections::HashMap;
+
+use super::DELTA;
+
+/// How a burst of particles looks and moves
+#[derive(Debug, Clone, Copy)]
+pub struct Emitter {
+    pub spr: &'static str,
+    /// How many particles are let out at once
+    pub count: u16,
+    /// Shortest and longest time in seconds a particle lives
+    pub life: (f32, f32),
+    /// Slowest and fastest speed in pixels per second a particle starts with
+    pub speed: (f32, f32),
+    /// How far in radians particles can fly off to either side of the direction
+    pub spread: f32,
+    /// How much of its speed a particle loses every second
+    pub drag: f32,
+    /// Colour when let out and when dying
+    pub colours: (Color, Color),
+    /// Scale when let out and when dying
+    pub sizes: (f32, f32),
+}
+
+/// Sprays out of someone who's been cut
+pub const BLOOD: Emitter = Emitter {
+    spr: "common/particle",
+    count: 24,
+    life: (0.3, 0.8),
+    speed: (80., 320.),
+    spread: 0.6,
+    drag: 6.,
+    colours: (Color{r: 0.8, g: 0., b: 0., a: 1.}, Color{r: 0.4, g: 0., b: 0., a: 0.}),
+    sizes: (1., 0.5),
+};
+
+/// Chips flying off a wall that was hit
+pub const IMPACT: Emitter = Emitter {
+    spr: "common/particle",
+    count: 12,
+    life: (0.15, 0.4),
+    speed: (120., 280.),
+    spread: 1.2,
+    drag: 8.,
+    colours: (Color{r: 1., g: 0.9, b: 0.6, a: 1.}, Color{r: 0.5, g: 0.5, b: 0.5, a: 0.}),
+    sizes: (0.6, 0.3),
+};
+
+/// Kicked up by footsteps
+pub const DUST: Emitter = Emitter {
+    spr: "common/particle",
+    count: 3,
+    life: (0.4, 0.7),
+    speed: (10., 40.),
+    spread: 1.,
+    drag: 3.,
+    colours: (Color{r: 0.6, g: 0.55, b: 0.5, a: 0.5}, Color{r: 0.6, g: 0.55, b: 0.5, a: 0.}),
+    sizes: (1., 2.),
+};
+
+/// Glowing sparks of magic
+pub const SPELL: Emitter = Emitter {
+    spr: "common/particle",
+    count: 20,
+    life: (0.5, 1.),
+    speed: (20., 90.),
+    spread: std::f32::consts::PI,
+    drag: 1.5,
+    colours: (Color{r: 0.5, g: 0.8, b: 1., a: 1.}, Color{r: 0.8, g: 0.4, b: 1., a: 0.}),
+    sizes: (0.8, 0.2),
+};
+
+#[derive(Debug, Clone)]
+struct Particle {
+    spr: &'static str,
+    pos: Point2,
+    vel: Vector2,
+    /// Seconds since it was let out
+    age: f32,
+    life: f32,
+    drag: f32,
+    colours: (Color, Color),
+    sizes: (f32, f32),
+}
+
+#[inline]
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// All the particles in the world, drawn in one batch per sprite
+#[derive(Default)]
+pub struct Particles {
+    particles: Vec<Particle>,
+    batches: HashMap<&'static str, SpriteBatch>,
+}
+
+impl Particles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Lets out a burst of particles flying off in the direction of the angle
+    pub fn emit(&mut self, emitter: &Emitter, pos: Point2, dir: f32) {
+        let mut rng = thread_rng();
+        let Emitter{spr, count, life, speed, spread, drag, colours, sizes} = *emitter;
+
+        self.particles.extend((0..count).map(|_| {
+            let angle = if spread > 0. { dir + rng.gen_range(-spread, spread) } else { dir };
+            let speed = if speed.1 > speed.0 { rng.gen_range(speed.0, speed.1) } else { speed.0 };
+            Particle {
+                spr,
+                pos,
+                vel: speed * angle_to_vec(angle),
+                age: 0.,
+                life: if life.1 > life.0 { rng.gen_range(life.0, life.1) } else { life.0 },
+                drag,
+                colours,
+                sizes,
+            }
+        }));
+    }
+    /// Moves and ages the particles, removing those that have died
+    pub fn update(&mut self) {
+        for p in &mut self.particles {
+            p.pos += p.vel * DELTA;
+            p.vel *= (1. - p.drag * DELTA).max(0.);
+            p.age += DELTA;
+        }
+        self.particles.retain(|p| p.age < p.life);
+    }
+    pub fn draw(&mut self, ctx: &mut Context, assets: &Assets) -> GameResult<()> {
+        for batch in self.batches.values_mut() {
+            batch.clear();
+        }
+        for p in &self.particles {
+            let batch = self.batches.entry(p.spr)
+                .or_insert_with(|| SpriteBatch::new(assets.get_img(ctx, p.spr).clone()));
+
+            let t = p.age / p.life;
+            let (from, to) = p.colours;
+            let size = lerp(p.sizes.0, p.sizes.1, t);
+            batch.add(DrawParam {
+                dest: p.pos.into(),
+                offset: Point2::new(0.5, 0.5).into(),
+                scale: Vector2::new(size, size).into(),
+                color: Color::new(lerp(from.r, to.r, t), lerp(from.g, to.g, t), lerp(from.b, to.b, t), lerp(from.a, to.a, t)),
+                .. Default::default()
+            });
+        }
+        if self.particles.is_empty() {
+            return Ok(())
+        }
+        for batch in self.batches.values() {
+            graphics::draw(ctx, batch, DrawParam::default())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io::{Read, Write};
+
+use ggez::{Context, GameResult, GameError, filesystem};
+
+use super::{DELTA, world::Statistics};
+
+/// Where the leaderboards are saved in the user data directory
+const LEADERBOARDS_FILE: &str = "/leaderboards.toml";
+/// How many of the best runs are kept for each level
+const LEADERBOARD_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    D,
+    C,
+    B,
+    A,
+    S,
+}
+
+impl Rank {
+    pub fn from_score(score: u32) -> Self {
+        match score {
+            1500..=u32::MAX => Rank::S,
+            1100..=1499 => Rank::A,
+            700..=1099 => Rank::B,
+            300..=699 => Rank::C,
+            _ => Rank::D,
+        }
+    }
+}
+
+impl Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Rank::*;
+        match *self {
+            S => "S".fmt(f),
+            A => "A".fmt(f),
+            B => "B".fmt(f),
+            C => "C".fmt(f),
+            D => "D".fmt(f),
+        }
+    }
+}
+
+/// Calculates the stealth score of a completed run
+///
+/// Going unseen and quick is rewarded, while getting spotted, raising alerts,
+/// taking damage and relying on pickups is penalised.
+pub fn score(stats: &Statistics) -> u32 {
+    let p = &stats.progress;
+    let secs = p.time as f32 * DELTA;
+
+    let mut score = 1000. + 100. * p.backstabs as f32;
+    if p.times_spotted == 0 {
+        score += 500.;
+    }
+    score -= 2. * secs;
+    score -= 150. * p.times_spotted as f32;
+    score -= 75. * p.alerts as f32;
+    score -= 25. * p.pickups_used as f32;
+    score -= 2. * p.damage_taken;
+
+    score.max(0.) as u32
+}
+
+/// A completed run as it is saved on the leaderboards
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    pub score: u32,
+    /// Time in ticks
+    pub time: usize,
+    pub enemies_killed: usize,
+    pub times_spotted: usize,
+    pub alerts: usize,
+    pub backstabs: usize,
+    pub pickups_used: usize,
+    pub damage_taken: f32,
+}
+
+impl Run {
+    pub fn new(stats: &Statistics) -> Self {
+        let p = &stats.progress;
+        Run {
+            score: score(stats),
+            time: p.time,
+            enemies_killed: stats.level.enemies.len() - stats.enemies_left,
+            times_spotted: p.times_spotted,
+            alerts: p.alerts,
+            backstabs: p.backstabs,
+            pickups_used: p.pickups_used,
+            damage_taken: p.damage_taken,
+        }
+    }
+    #[inline]
+    pub fn rank(&self) -> Rank {
+        Rank::from_score(self.score)
+    }
+    /// Whether this run should be placed above the other on a leaderboard
+    fn beats(&self, other: &Self) -> bool {
+        self.score > other.score || (self.score == other.score && self.time < other.time)
+    }
+}
+
+impl Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>5} {} {:>6.1}s", self.score, self.rank(), self.time as f32 * DELTA)
+    }
+}
+
+/// The best runs of every level that has been completed, keyed by the level's content hash
+///
+/// Since the hash is of the level's contents, editing a level gives it a fresh leaderboard.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Leaderboards {
+    levels: HashMap<String, Vec<Run>>,
+}
+
+#[inline]
+fn key(level_hash: u64) -> String {
+    format!("{:016x}", level_hash)
+}
+
+impl Leaderboards {
+    pub fn load(ctx: &mut Context) -> GameResult<Self> {
+        if !filesystem::exists(ctx, LEADERBOARDS_FILE) {
+            return Ok(Self::default())
+        }
+        let mut s = String::new();
+        filesystem::open(ctx, LEADERBOARDS_FILE)?.read_to_string(&mut s)?;
+
+        toml::from_str(&s).map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))
+    }
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let s = toml::to_string(self).map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?;
+        filesystem::create(ctx, LEADERBOARDS_FILE)?.write_all(s.as_bytes())?;
+        Ok(())
+    }
+    /// The runs of a level from best to worst
+    pub fn get(&self, level_hash: u64) -> &[Run] {
+        self.levels.get(&key(level_hash)).map(|v| &**v).unwrap_or(&[])
+    }
+    #[inline]
+    pub fn personal_best(&self, level_hash: u64) -> Option<Run> {
+        self.get(level_hash).first().copied()
+    }
+    /// Puts the run on the level's leaderboard, returning its placement if it's good enough to be kept
+    pub fn submit(&mut self, level_hash: u64, run: Run) -> Option<usize> {
+        let runs = self.levels.entry(key(level_hash)).or_default();
+
+        let place = runs.iter().position(|r| run.beats(r)).unwrap_or(runs.len());
+        if place >= LEADERBOARD_SIZE {
+            return None
+        }
+        runs.insert(place, run);
+        runs.truncate(LEADERBOARD_SIZE);
+        Some(place)
+    }
+    /// Removes the leaderboard of a level, returning whether it had one
+    pub fn clear(&mut self, level_hash: u64) -> bool {
+        self.levels.remove(&key(level_hash)).is_some()
+    }
+    pub fn clear_all(&mut self) {
+        self.levels.clear();
+    }
+    /// The hashes of all levels with runs and their amount of runs
+    pub fn levels(&self) -> impl Iterator<Item=(&str, usize)> {
+        self.levels.iter().map(|(k, v)| (&**k, v.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(score: u32, time: usize) -> Run {
+        Run {
+            score,
+            time,
+            enemies_killed: 0,
+            times_spotted: 0,
+            alerts: 0,
+            backstabs: 0,
+            pickups_used: 0,
+            damage_taken: 0.,
+        }
+    }
+
+    #[test]
+    fn ranks() {
+        assert_eq!(Rank::from_score(0), Rank::D);
+        assert_eq!(Rank::from_score(299), Rank::D);
+        assert_eq!(Rank::from_score(300), Rank::C);
+        assert_eq!(Rank::from_score(1099), Rank::B);
+        assert_eq!(Rank::from_score(1100), Rank::A);
+        assert_eq!(Rank::from_score(1500), Rank::S);
+        assert_eq!(Rank::from_score(u32::MAX), Rank::S);
+    }
+
+    #[test]
+    fn runs_are_kept_best_first() {
+        let mut boards = Leaderboards::default();
+        assert_eq!(boards.submit(1, run(500, 100)), Some(0));
+        assert_eq!(boards.submit(1, run(900, 100)), Some(0));
+        assert_eq!(boards.submit(1, run(500, 50)), Some(1));
+        assert_eq!(boards.submit(2, run(0, 0)), Some(0));
+
+        let runs = boards.get(1);
+        assert_eq!(runs.len(), 3);
+        assert_eq!((runs[0].score, runs[1].time, runs[2].time), (900, 50, 100));
+        assert_eq!(boards.personal_best(1), Some(runs[0]));
+        assert_eq!(boards.get(3), &[]);
+    }
+
+    #[test]
+    fn only_the_best_runs_are_kept() {
+        let mut boards = Leaderboards::default();
+        for i in 0..LEADERBOARD_SIZE as u32 {
+            assert_eq!(boards.submit(1, run(100 + i, 100)), Some(0));
+        }
+        assert_eq!(boards.submit(1, run(0, 100)), None);
+        assert_eq!(boards.submit(1, run(150, 50)), Some(LEADERBOARD_SIZE - 51));
+
+        let runs = boards.get(1);
+        assert_eq!(runs.len(), LEADERBOARD_SIZE);
+        assert_eq!(runs.last().unwrap().score, 101);
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::{Read, Write};
+
+use ggez::{
+    Context, GameResult, GameError,
+    filesystem,
+    conf::{WindowMode, FullscreenType},
+    graphics::{self, Rect},
+};
+
+use crate::io::error::ContentError;
+
+use super::controls::Controls;
+
+const SETTINGS_FILE: &str = "/settings.toml";
+
+/// The resolutions that can be picked in the options
+pub const RESOLUTIONS: [(f32, f32); 5] = [
+    (1152., 648.),
+    (1280., 720.),
+    (1366., 768.),
+    (1600., 900.),
+    (1920., 1080.),
+];
+
+/// The player's settings, saved in the user's config directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Volumes from 0 to 1
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub effects_volume: f32,
+    pub fullscreen: bool,
+    pub width: f32,
+    pub height: f32,
+    /// How zoomed in the camera is when playing
+    pub zoom: f32,
+    /// Whether what the player can't see is hidden
+    pub fog_of_war: bool,
+    pub controls: Controls,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            master_volume: 1.,
+            music_volume: 1.,
+            effects_volume: 1.,
+            fullscreen: false,
+            width: RESOLUTIONS[0].0,
+            height: RESOLUTIONS[0].1,
+            zoom: 1.,
+            fog_of_war: true,
+            controls: Controls::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(ctx: &mut Context) -> GameResult<Self> {
+        if !filesystem::exists(ctx, SETTINGS_FILE) {
+            return Ok(Self::default())
+        }
+        let mut s = String::new();
+        filesystem::open(ctx, SETTINGS_FILE)?.read_to_string(&mut s)?;
+
+        toml::from_str(&s).map_err(|e| ContentError::toml("settings.toml", &e).into())
+    }
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let s = toml::to_string(self).map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?;
+        filesystem::create(ctx, SETTINGS_FILE)?.write_all(s.as_bytes())?;
+        Ok(())
+    }
+    pub fn window_mode(&self) -> WindowMode {
+        WindowMode::default()
+            .dimensions(self.width, self.height)
+            .fullscreen_type(if self.fullscreen { FullscreenType::Desktop } else { FullscreenType::Windowed })
+            .resizable(true)
+    }
+    /// Changes the window to the settings' mode and resolution
+    pub fn apply_window(&self, ctx: &mut Context) -> GameResult<()> {
+        graphics::set_mode(ctx, self.window_mode())?;
+        graphics::set_screen_coordinates(ctx, Rect::new(0., 0., self.width, self.height))
+    }
+}
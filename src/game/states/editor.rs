@@ -1,16 +1,14 @@
 use crate::{
     util::{
-        ver,
-        hor,
         sstr,
-        TRANS,
-        Vector2, Point2},
-    io::tex::PosText,
+        TRANS, RED, GREEN, BLUE, YELLOW,
+        Vector2, Point2, Rotation2},
+    io::{tex::PosText, res, error::ContentError},
     ext::BoolExt,
     game::{
-        DELTA, Content, GameState, State, StateSwitch,
-        world::{Grid, Level, Palette},
-        event::{Event::{self, Key, Mouse}, MouseButton as Mb, KeyCode, KeyMods}
+        DELTA, Content, GameState, State, StateSwitch, minimap::Minimap, controls::Action,
+        world::{Anchor, Grid, Layer, Level, Lighting, Palette, Objective, Prefab},
+        event::{Event::{self, Key, Mouse, Wheel, Text}, MouseButton as Mb, KeyCode, KeyMods}
     },
     obj::{Object, enemy::Enemy, decal::Decal, pickup::PICKUPS}
 };
@@ -24,9 +22,14 @@ use ggez::{
     },
 };
 
+use std::f32::consts::PI;
 use std::path::PathBuf;
-use std::io::Read;
-use std::fs::File;
+use clipboard::{ClipboardContext, ClipboardProvider};
+
+pub mod history;
+mod picker;
+use self::history::{History, Command, Entity, TileChange};
+use self::picker::MaterialPicker;
 
 #[derive(Debug, PartialEq, Clone)]
 enum Tool {
@@ -36,7 +39,7 @@ enum Tool {
 
 #[derive(Debug, Clone, Copy)]
 enum Insertion {
-    Material(u8),
+    Material(u16),
     Intel,
     Enemy{rot: f32},
     Pickup(u8),
@@ -71,8 +74,33 @@ impl ::std::cmp::PartialEq for Insertion {
     }
 }
 
+/// How materials are painted
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PaintTool {
+    /// One tile at a time while the mouse is held
+    Brush,
+    Rect,
+    HollowRect,
+    Line,
+    /// All connected tiles of the same material
+    Fill,
+}
+
+impl PaintTool {
+    fn name(self) -> &'static str {
+        match self {
+            PaintTool::Brush => "Brush",
+            PaintTool::Rect => "Rectangle",
+            PaintTool::HollowRect => "Hollow rectangle",
+            PaintTool::Line => "Line",
+            PaintTool::Fill => "Fill",
+        }
+    }
+}
+
+/// Entities picked with the selector tool, by their indices in the level
 #[derive(Default, Debug, PartialEq, Clone)]
-struct Selection {
+pub struct Selection {
     exit: bool,
     enemies: Vec<usize>,
     intels: Vec<usize>,
@@ -97,13 +125,31 @@ pub struct Editor {
     save: PathBuf,
     pos: Point2,
     level: Level,
+    history: History,
     current: Tool,
-    mat_text: PosText,
+    picker: MaterialPicker,
+    paint_text: PosText,
+    paint_tool: PaintTool,
+    /// The layer that is painted on
+    layer: Layer,
+    /// Which layers are drawn, in the order of `Layer::ALL`
+    visible_layers: [bool; 3],
+    /// Tile where the shape being drawn started
+    shape_start: Option<(u16, u16)>,
+    /// Where the box selection being dragged started
+    marquee: Option<Point2>,
     entities_bar: InsertionBar,
     extra_bar: InsertionBar,
     draw_visibility_cones: bool,
     rotation_speed: f32,
     snap_on_grid: bool,
+    minimap: Minimap,
+    /// The history revision the minimap was drawn for, none if it has to be drawn again regardless
+    minimap_revision: Option<u32>,
+    /// Whether the view is being dragged around on the minimap
+    navigating: bool,
+    /// The lighting preview, if shown, with the history revision it was made for
+    lighting: Option<(u32, Lighting)>,
 }
 
 
@@ -156,35 +202,11 @@ impl InsertionBar {
 
 impl Editor {
     #[allow(clippy::new_ret_no_self)]
-    pub fn new(s: &State, level: Option<Level>) -> GameResult<Box<dyn GameState>> {
-        let mat_text = s.assets.text(Point2::new(2., 18.0)).and_text("Materials:");
-        let mut entities = vec![
-            Insertion::Enemy{rot: 0.},
-            Insertion::Exit,
-            Insertion::Intel,
-            Insertion::Pickup(0),
-            Insertion::Pickup(1),
-            Insertion::Pickup(2),
-            Insertion::Pickup(3),
-            Insertion::Pickup(4),
-            Insertion::Pickup(5),
-        ];
-
-        let EditorFile{palettes: EditorPalettes{materials, decals}} = {
-            let mut file = File::open("resources/editor.toml").unwrap();
-            let mut s = String::new();
-            file.read_to_string(&mut s).unwrap();
-            
-            toml::from_str(&s).unwrap()
-        };
-        entities.extend(decals.into_iter().map(|dec| Insertion::Decal{rot: 0., spr: sstr(dec)}));
-
-        let extra_entities = entities.drain(20..).collect();
-
-        let entities_bar = InsertionBar::new(Point2::new(392., 18.0), s, "Entitites:", entities.into_boxed_slice());
-        let extra_bar = InsertionBar::new(Point2::new(392., 52.0), s, "", extra_entities);
-
-        let palette = Palette::new(materials.into_iter().map(sstr).collect());
+    pub fn new(ctx: &mut Context, s: &State, level: Option<Level>) -> GameResult<Box<dyn GameState>> {
+        let paint_text = s.assets.text_sized(Point2::new(2., 44.0), 14.).and_text(PaintTool::Brush.name())
+            .and_text(" on ")
+            .and_text(Layer::Wall.name());
+        let (entities_bar, extra_bar, palette) = load_config(s)?;
 
         let save;
         if let Content::File(ref f) = s.content {
@@ -193,10 +215,16 @@ impl Editor {
             return Err(GameError::ResourceLoadError("Cannot load editor without file".to_owned()));
         }
 
-        let mut level = level
-            .or_else(|| Level::load(&save).ok())
-            .unwrap_or_else(|| Level::new(palette.clone(), 32, 32));
-        level.palette = level.grid.migrate(&level.palette, palette);
+        // A new level is only made if there isn't one, so a broken one isn't saved over
+        let mut level = match level {
+            Some(level) => level,
+            None if save.exists() => Level::load(&save)?,
+            None => Level::new(palette.clone(), 32, 32),
+        };
+        level.migrate(palette);
+        s.assets.preload(ctx, level.tile_sprites(), level.object_sprites());
+        let picker = MaterialPicker::new(s, &level.palette);
+        let minimap = Minimap::new(ctx, &level.palette, &level.floor, &level.grid, None)?;
 
         let x = f32::from(level.grid.width()) * 16.;
         let y = f32::from(level.grid.height()) * 16.;
@@ -206,16 +234,27 @@ impl Editor {
             pos: Point2::new(x, y),
             current: Tool::Selector(Selection::default()),
             draw_visibility_cones: false,
-            mat_text,
+            picker,
+            paint_text,
+            paint_tool: PaintTool::Brush,
+            layer: Layer::Wall,
+            visible_layers: [true; 3],
+            shape_start: None,
+            marquee: None,
             entities_bar,
             extra_bar,
             level,
+            history: History::default(),
             rotation_speed: 0.,
             snap_on_grid: false,
+            minimap,
+            minimap_revision: Some(0),
+            navigating: false,
+            lighting: None,
         }))
     }
     fn mousepos(&self, s: &State) -> Point2 {
-        let mut mp = s.mouse - s.offset;
+        let mut mp = s.mouse_world();
         if self.snap_on_grid {
             mp.x = (mp.x / 32.).floor() * 32. + 16.;
             mp.y = (mp.y / 32.).floor() * 32. + 16.;
@@ -224,13 +263,93 @@ impl Editor {
     }
 }
 
+/// Reads `editor.toml` for the bars of entities and the default palette
+fn load_config(s: &State) -> Result<(InsertionBar, InsertionBar, Palette), ContentError> {
+    let mut entities = vec![
+        Insertion::Enemy{rot: 0.},
+        Insertion::Exit,
+        Insertion::Intel,
+        Insertion::Pickup(0),
+        Insertion::Pickup(1),
+        Insertion::Pickup(2),
+        Insertion::Pickup(3),
+        Insertion::Pickup(4),
+        Insertion::Pickup(5),
+    ];
+
+    let text = res::read_to_string("editor.toml").map_err(|e| ContentError::io("editor.toml", e))?;
+    let EditorFile{palettes: EditorPalettes{materials, decals}} = toml::from_str(&text)
+        .map_err(|e| ContentError::toml("editor.toml", &e))?;
+    entities.extend(decals.into_iter().map(|dec| Insertion::Decal{rot: 0., spr: sstr(dec)}));
+
+    let extra_entities = entities.drain(20.min(entities.len())..).collect();
+
+    let entities_bar = InsertionBar::new(Point2::new(392., 18.0), s, "Entitites:", entities.into_boxed_slice());
+    let extra_bar = InsertionBar::new(Point2::new(392., 52.0), s, "", extra_entities);
+
+    Ok((entities_bar, extra_bar, Palette::new(materials.into_iter().map(sstr).collect())))
+}
+
 const START_X: f32 = 103.;
-const YELLOW: Color = Color{r: 1., g: 1., b: 0., a: 1.};
+/// How far the mouse has to be dragged for it to be a box selection rather than a click
+const MARQUEE_THRESHOLD: f32 = 4.;
+/// How much one step of the scroll wheel zooms
+const ZOOM_STEP: f32 = 1.1;
+/// The rectangle between two corners of a box selection
+fn marquee_rect(a: Point2, b: Point2) -> Rect {
+    Rect::new(a.x.min(b.x), a.y.min(b.y), (a.x - b.x).abs(), (a.y - b.y).abs())
+}
+
+/// The corners of the bounding box of the objects
+fn bounds(objs: &[Object]) -> Option<(Point2, Point2)> {
+    let first = objs.first()?.pos;
+    Some(objs.iter().fold((first, first), |(min, max), o| {
+        (Point2::new(min.x.min(o.pos.x), min.y.min(o.pos.y)), Point2::new(max.x.max(o.pos.x), max.y.max(o.pos.y)))
+    }))
+}
+
+/// Adds everything inside the rectangle to the selection
+fn select_in(level: &Level, selection: &mut Selection, rect: Rect) {
+    let inside = |p: Point2| rect.contains(p);
+    fn add(v: &mut Vec<usize>, i: usize) {
+        if !v.contains(&i) {
+            v.push(i);
+        }
+    }
+
+    for (i, _) in level.enemies.iter().enumerate().filter(|(_, e)| inside(e.pl.obj.pos)) {
+        add(&mut selection.enemies, i);
+    }
+    for (i, _) in level.intels.iter().enumerate().filter(|(_, &p)| inside(p)) {
+        add(&mut selection.intels, i);
+    }
+    for (i, _) in level.decals.iter().enumerate().filter(|(_, d)| inside(d.obj.pos)) {
+        add(&mut selection.decals, i);
+    }
+    for (i, _) in level.pickups.iter().enumerate().filter(|(_, p)| inside(p.0)) {
+        add(&mut selection.pickups, i);
+    }
+    if level.exit.is_some_and(inside) {
+        selection.exit = true;
+    }
+}
 
 impl GameState for Editor {
-    fn update(&mut self, _s: &mut State, ctx: &mut Context) -> GameResult<()> {
+    fn update(&mut self, s: &mut State, ctx: &mut Context) -> GameResult<()> {
         let speed = if keyboard::is_mod_active(ctx, KeyMods::SHIFT) { 315. } else { 175. };
-        let v = speed * Vector2::new(hor(ctx), ver(ctx));
+        let controls = &s.settings.controls;
+        // The nudging inputs move the selection instead when there is one
+        let dir = if self.picker.searching {
+            Vector2::new(0., 0.)
+        } else if self.has_selection() {
+            Vector2::new(
+                controls.axis_except(ctx, &s.pad, Action::MoveX, Some(Action::NudgeX)),
+                controls.axis_except(ctx, &s.pad, Action::MoveY, Some(Action::NudgeY)),
+            )
+        } else {
+            controls.movement(ctx, &s.pad)
+        };
+        let v = speed * dir;
         self.pos += v * DELTA;
 
         match self.current {
@@ -238,32 +357,52 @@ impl GameState for Editor {
             Tool::Inserter(Insertion::Decal{ref mut rot, ..}) => *rot += self.rotation_speed * DELTA,
             _ => (),
         }
+
+        self.refresh_lighting(false);
+
+        let revision = self.history.revision();
+        if self.minimap_revision != Some(revision) {
+            self.minimap_revision = Some(revision);
+            self.minimap.refresh(ctx, &self.level.palette, &self.level.floor, &self.level.grid, None)?;
+        }
         Ok(())
     }
     fn logic(&mut self, s: &mut State, ctx: &mut Context) -> GameResult<()> {
-        if mouse::button_pressed(ctx, Mb::Left) && s.mouse.y > 64. {
+        if self.navigating {
+            self.pos = self.minimap.to_world(s, s.mouse);
+        } else if mouse::button_pressed(ctx, Mb::Left) && s.mouse.y > 64. && self.paint_tool == PaintTool::Brush {
             if let Tool::Inserter(Insertion::Material(mat)) = self.current {
-                let (mx, my) = Grid::snap(s.mouse - s.offset);
-                self.level.grid.insert(mx, my, mat);
+                let (mx, my) = Grid::snap(s.mouse_world());
+                self.history.paint(&mut self.level, self.layer, mx, my, mat);
             }
         }
 
-        s.focus_on(self.pos);
+        s.camera.focus_on(self.pos);
         Ok(())
     }
 
     #[allow(clippy::cognitive_complexity)]
     fn draw(&mut self, s: &State, ctx: &mut Context) -> GameResult<()> {
-        self.level.grid.draw(&self.level.palette, ctx, &s.assets)?;
+        for (&layer, _) in Layer::ALL.iter().zip(&self.visible_layers).filter(|&(_, &visible)| visible) {
+            self.level.layer(layer).draw(&self.level.palette, ctx, &s.assets, s.view())?;
+        }
 
         if let Tool::Inserter(Insertion::Material(mat)) = self.current {
-            let (x, y) = Grid::snap(s.mouse-s.offset);
-            let x = f32::from(x) * 32.;
-            let y = f32::from(y) * 32.;
-            self.level.palette.draw_mat(mat, ctx, &s.assets, x, y, graphics::DrawParam {
-                color: TRANS,
-                .. Default::default()
-            })?;
+            let tiles = self.shape_tiles(s).unwrap_or_else(|| vec![Grid::snap(s.mouse_world())]);
+
+            for (x, y) in tiles {
+                let x = f32::from(x) * 32.;
+                let y = f32::from(y) * 32.;
+                if mat == Grid::EMPTY {
+                    let mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(1.), Rect{x, y, w: 32., h: 32.}, RED)?;
+                    graphics::draw(ctx, &mesh, DrawParam::default())?;
+                } else {
+                    self.level.palette.draw_mat(mat, ctx, &s.assets, x, y, graphics::DrawParam {
+                        color: TRANS,
+                        .. Default::default()
+                    })?;
+                }
+            }
         }
 
         if let Some(start) = self.level.start_point {
@@ -314,6 +453,10 @@ impl GameState for Editor {
             if self.draw_visibility_cones {
                 enemy.draw_visibility_cone(ctx, 512.)?;
             }
+            if self.level.objectives.contains(&Objective::Kill(i)) {
+                let mesh = Mesh::new_circle(ctx, DrawMode::stroke(2.), enemy.pl.obj.pos, 20., 0.5, RED)?;
+                graphics::draw(ctx, &mesh, DrawParam::default())?;
+            }
             enemy.draw(ctx, &s.assets, WHITE)?;
         }
         for (i, decal) in self.level.decals.iter().enumerate() {
@@ -387,16 +530,32 @@ impl GameState for Editor {
             }
         }
 
+        if let Some(start) = self.marquee {
+            let rect = marquee_rect(start, s.mouse_world());
+            if rect.w > 0. && rect.h > 0. {
+                let mesh = Mesh::new_rectangle(ctx, DrawMode::stroke(1.), rect, YELLOW)?;
+                graphics::draw(ctx, &mesh, DrawParam::default())?;
+            }
+        }
+        if let Some((_, ref mut lighting)) = self.lighting {
+            lighting.render(ctx)?;
+        }
+
         Ok(())
     }
     fn draw_hud(&mut self, s: &State, ctx: &mut Context) -> GameResult<()> {
-        let dest = (self.mousepos(s) + s.offset).into();
+        if let Some((_, ref lighting)) = self.lighting {
+            lighting.draw(ctx, s.width, s.height)?;
+        }
+        let dest = s.camera.to_screen(self.mousepos(s)).into();
+        let scale = [s.camera.zoom(); 2].into();
         match self.current {
             Tool::Selector(_) => (),
             Tool::Inserter(Insertion::Material(_)) => (),
             Tool::Inserter(Insertion::Pickup(index)) => {
                 let drawparams = graphics::DrawParam {
                     dest,
+                    scale,
                     rotation: 0.,
                     offset: Point2::new(0.5, 0.5).into(),
                     color: TRANS,
@@ -408,6 +567,7 @@ impl GameState for Editor {
             Tool::Inserter(Insertion::Enemy{rot}) => {
                 let drawparams = graphics::DrawParam {
                     dest,
+                    scale,
                     rotation: rot,
                     offset: Point2::new(0.5, 0.5).into(),
                     color: TRANS,
@@ -419,6 +579,7 @@ impl GameState for Editor {
             Tool::Inserter(Insertion::Decal{spr, rot}) => {
                 let drawparams = graphics::DrawParam {
                     dest,
+                    scale,
                     rotation: rot,
                     offset: Point2::new(0.5, 0.5).into(),
                     color: TRANS,
@@ -430,6 +591,7 @@ impl GameState for Editor {
             Tool::Inserter(Insertion::Exit) => {
                 let drawparams = graphics::DrawParam {
                     dest,
+                    scale,
                     offset: Point2::new(0.5, 0.5).into(),
                     color: TRANS,
                     .. Default::default()
@@ -440,6 +602,7 @@ impl GameState for Editor {
             Tool::Inserter(Insertion::Intel) => {
                 let drawparams = graphics::DrawParam {
                     dest,
+                    scale,
                     offset: Point2::new(0.5, 0.5).into(),
                     color: TRANS,
                     .. Default::default()
@@ -452,104 +615,170 @@ impl GameState for Editor {
         let mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect{x:0.,y:0.,h: 64., w: s.width as f32}, Color{r: 0.5, g: 0.5, b: 0.5, a: 1.})?;
         graphics::draw(ctx, &mesh, DrawParam::default())?;
 
-        for mat in 0..self.level.palette.len() as u8 {
-            let x = START_X + f32::from(mat) * 36.;
-
-            if Tool::Inserter(Insertion::Material(mat)) == self.current {
-                let mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect{x: x - 1., y: 15., w: 34., h: 34.}, YELLOW)?;
-                graphics::draw(ctx, &mesh, DrawParam::default())?;
-            }
-            self.level.palette.draw_mat(mat, ctx, &s.assets, x, 16., DrawParam::default())?;
-        }
+        let current_mat = match self.current {
+            Tool::Inserter(Insertion::Material(mat)) => self.level.palette.get(mat),
+            _ => None,
+        };
+        self.picker.draw(ctx, s, current_mat)?;
 
         self.entities_bar.draw(ctx, s, if let Tool::Inserter(ins) = self.current{Some(ins)}else{None})?;
         self.extra_bar.draw(ctx, s, if let Tool::Inserter(ins) = self.current{Some(ins)}else{None})?;
 
-        self.mat_text.draw_text(ctx)?;
+        self.paint_text.draw_text(ctx)?;
         self.entities_bar.ent_text.draw_text(ctx)?;
-        self.extra_bar.ent_text.draw_text(ctx)
+        self.extra_bar.ent_text.draw_text(ctx)?;
+
+        let mut markers: Vec<_> = self.level.enemies.iter().map(|e| (e.pl.obj.pos, RED)).collect();
+        markers.extend(self.level.intels.iter().map(|&p| (p, YELLOW)));
+        markers.extend(self.level.exit.map(|p| (p, BLUE)));
+        markers.extend(self.level.start_point.map(|p| (p, GREEN)));
+        self.minimap.draw(ctx, s, &markers)
     }
     #[allow(clippy::cognitive_complexity)]
     fn event_up(&mut self, s: &mut State, ctx: &mut Context, event: Event) {
         let shift = keyboard::is_mod_active(ctx, KeyMods::SHIFT);
         let ctrl = keyboard::is_mod_active(ctx, KeyMods::CTRL);
+        let alt = keyboard::is_mod_active(ctx, KeyMods::ALT);
+        let is = |s: &State, ctx: &Context, action| s.settings.controls.matches(ctx, action, &event);
 
-        use self::KeyCode::*;
-        match event {
-            Key(Z) => self.level.save(&self.save).unwrap(),
-            Key(X) => self.level = Level::load(&self.save).unwrap(),
-            Key(C) => self.draw_visibility_cones.toggle(),
-            Key(G) => self.snap_on_grid.toggle(),
-            Key(P) => {
-                s.switch(StateSwitch::Play(self.level.clone()));
+        use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+        if self.picker.searching {
+            match event {
+                Text(c) => self.picker.type_char(c),
+                Key(KeyCode::Back) => self.picker.backspace(),
+                _ if is(s, ctx, Action::Confirm) => self.picker.end_search(false),
+                _ if is(s, ctx, Action::Back) => self.picker.end_search(true),
+                _ => (),
             }
-            Key(T) => self.current = Tool::Selector(Selection::default()),
-            Key(Delete) | Key(Back) => if let Tool::Selector(ref mut selection) = self.current {
-                #[allow(clippy::unneeded_field_pattern)]
-                let Selection {
-                    mut enemies,
-                    mut intels,
-                    mut pickups,
-                    mut decals,
-                    exit, moving: _,
-                } = ::std::mem::replace(selection, Selection::default());
-
-                if exit {
-                    self.level.exit = None;
-                }
-                enemies.sort();
-                for enemy in enemies.into_iter().rev() {
-                    self.level.enemies.remove(enemy);
-                }
-                intels.sort();
-                for intel in intels.into_iter().rev() {
-                    self.level.intels.remove(intel);
+            return
+        }
+        // Actions bound to chords are checked before the ones they would also match
+        match event {
+            Wheel(y) if self.picker.in_row(s.mouse) => self.picker.scroll(-y.signum() as isize),
+            Wheel(y) => s.camera.set_zoom(s.camera.zoom() * ZOOM_STEP.powf(y)),
+            Text(_) => (),
+            Mouse(Mb::Left) if self.navigating => self.navigating = false,
+            _ if is(s, ctx, Action::Search) => self.picker.start_search(),
+            _ if !self.has_selection() && is(s, ctx, Action::Map) => self.minimap.toggle(),
+            _ if is(s, ctx, Action::Undo) => {
+                let changed = self.history.undo(&mut self.level);
+                if changed {
+                    self.deselect();
                 }
-                decals.sort();
-                for decal in decals.into_iter().rev() {
-                    self.level.decals.remove(decal);
+                self.forget_lost_material();
+            }
+            _ if is(s, ctx, Action::Redo) => {
+                let changed = self.history.redo(&mut self.level);
+                if changed {
+                    self.deselect();
                 }
-                pickups.sort();
-                for pickup in pickups.into_iter().rev() {
-                    self.level.pickups.remove(pickup);
+                self.forget_lost_material();
+            }
+            _ if is(s, ctx, Action::Copy) => self.copy(),
+            _ if is(s, ctx, Action::Cut) => {
+                self.copy();
+                if let Tool::Selector(ref mut selection) = self.current {
+                    let selection = std::mem::take(selection);
+                    let cmd = self.delete_command(selection);
+                    self.history.execute(&mut self.level, cmd);
                 }
             }
-            Key(Comma) => {
-                self.rotation_speed = 0.;
-                if shift {
-                    match self.current {
-                        Tool::Inserter(Insertion::Enemy{ref mut rot}) => *rot -= std::f32::consts::FRAC_PI_4,
-                        Tool::Inserter(Insertion::Decal{ref mut rot, ..}) => *rot -= std::f32::consts::FRAC_PI_4,
-                        _ => (),
+            _ if is(s, ctx, Action::Paste) => self.paste(s),
+            _ if is(s, ctx, Action::Save) => if let Err(e) = self.level.save(&self.save) {
+                error!("Couldn't save level: {}", e);
+            },
+            _ if is(s, ctx, Action::Revert) => {
+                match Level::load(&self.save) {
+                    Ok(to) => {
+                        let from = Box::new(self.level.clone());
+                        self.history.execute(&mut self.level, Command::ReplaceLevel{from, to: Box::new(to)});
+                        self.deselect();
                     }
+                    Err(e) => error!("Couldn't reload level: {}", e),
                 }
             }
-            Key(Period) => {
-                self.rotation_speed = 0.;
-                if shift {
-                    match self.current {
-                        Tool::Inserter(Insertion::Enemy{ref mut rot}) => *rot += std::f32::consts::FRAC_PI_4,
-                        Tool::Inserter(Insertion::Decal{ref mut rot, ..}) => *rot += std::f32::consts::FRAC_PI_4,
-                        _ => (),
+            _ if is(s, ctx, Action::Cones) => self.draw_visibility_cones.toggle(),
+            _ if is(s, ctx, Action::SnapToGrid) => self.snap_on_grid.toggle(),
+            _ if is(s, ctx, Action::Lighting) => {
+                self.lighting = match self.lighting {
+                    Some(_) => None,
+                    None => Some((self.history.revision(), self.make_lighting())),
+                };
+            }
+            _ if is(s, ctx, Action::Darker) => self.change_ambient(-0.1),
+            _ if is(s, ctx, Action::Lighter) => self.change_ambient(0.1),
+            _ if is(s, ctx, Action::Playtest) => {
+                s.switch(StateSwitch::Play(self.level.clone()));
+            }
+            _ if is(s, ctx, Action::SelectTool) => self.current = Tool::Selector(Selection::default()),
+            _ if is(s, ctx, Action::BrushTool) => self.set_paint_tool(PaintTool::Brush),
+            _ if is(s, ctx, Action::RectTool) => self.set_paint_tool(PaintTool::Rect),
+            _ if is(s, ctx, Action::HollowRectTool) => self.set_paint_tool(PaintTool::HollowRect),
+            _ if is(s, ctx, Action::LineTool) => self.set_paint_tool(PaintTool::Line),
+            _ if is(s, ctx, Action::FillTool) => self.set_paint_tool(PaintTool::Fill),
+            _ if is(s, ctx, Action::Eraser) => self.current = Tool::Inserter(Insertion::Material(Grid::EMPTY)),
+            _ if is(s, ctx, Action::FloorLayer) => self.choose_layer(0, shift),
+            _ if is(s, ctx, Action::WallLayer) => self.choose_layer(1, shift),
+            _ if is(s, ctx, Action::OverlayLayer) => self.choose_layer(2, shift),
+            _ if is(s, ctx, Action::Target) => if let Tool::Selector(ref selection) = self.current {
+                // Toggle whether the selected enemies are targets to be assassinated
+                let mut objectives = self.level.objectives.clone();
+                for &i in &selection.enemies {
+                    let target = Objective::Kill(i);
+                    if let Some(j) = objectives.iter().position(|&o| o == target) {
+                        objectives.remove(j);
+                    } else {
+                        objectives.push(target);
                     }
                 }
+                let from = self.level.objectives.clone();
+                self.history.execute(&mut self.level, Command::SetObjectives{from, to: objectives});
+            }
+            _ if is(s, ctx, Action::Delete) => if let Tool::Selector(ref mut selection) = self.current {
+                let selection = std::mem::take(selection);
+                let cmd = self.delete_command(selection);
+                self.history.execute(&mut self.level, cmd);
+            }
+            _ if self.has_selection() && is(s, ctx, Action::RotateLeft) => self.rotate_selection(if shift { -FRAC_PI_4 } else { -FRAC_PI_2 }),
+            _ if self.has_selection() && is(s, ctx, Action::RotateRight) => self.rotate_selection(if shift { FRAC_PI_4 } else { FRAC_PI_2 }),
+            _ if shift && is(s, ctx, Action::Mirror) => self.transform_selection(|c, obj| {
+                obj.pos.y = 2. * c.y - obj.pos.y;
+                obj.rot = -obj.rot;
+            }),
+            _ if is(s, ctx, Action::Mirror) => self.transform_selection(|c, obj| {
+                obj.pos.x = 2. * c.x - obj.pos.x;
+                obj.rot = PI - obj.rot;
+            }),
+            _ if is(s, ctx, Action::RotateLeft) => self.stop_rotating(if shift { -FRAC_PI_4 } else { 0. }),
+            _ if is(s, ctx, Action::RotateRight) => self.stop_rotating(if shift { FRAC_PI_4 } else { 0. }),
+            _ if is(s, ctx, Action::NudgeX) || is(s, ctx, Action::NudgeY) => {
+                let controls = &s.settings.controls;
+                let dir = Vector2::new(controls.axis_event(ctx, Action::NudgeX, &event), controls.axis_event(ctx, Action::NudgeY, &event));
+                self.nudge(dir, ctrl, shift, alt);
+            }
+            _ if is(s, ctx, Action::SetStart) => {
+                let cmd = Command::SetStart{from: self.level.start_point, to: Some(self.mousepos(s))};
+                self.history.execute(&mut self.level, cmd);
+            }
+            Mouse(Mb::Left) => {
+                self.history.end_stroke();
+                self.finish_shape(s);
+                self.click(s, ctx);
+                self.marquee = None;
             }
-            Key(Up) if ctrl => self.level.grid.shorten(),
-            Key(Down) if ctrl => self.level.grid.heighten(),
-            Key(Left) if ctrl => self.level.grid.thin(),
-            Key(Right) if ctrl => self.level.grid.widen(),
-            Mouse(Mb::Middle) | Key(Q) => self.level.start_point = Some(self.mousepos(&s)),
-            Mouse(Mb::Left) => self.click(s, ctx),
             _ => (),
         }
     }
     fn event_down(&mut self, s: &mut State, ctx: &mut Context, event: Event) {
-        use self::KeyCode::*;
+        if self.picker.searching {
+            return
+        }
 
         let shift = keyboard::is_mod_active(ctx, KeyMods::SHIFT);
         let mousepos = self.mousepos(&s);
 
         match event {
+            Mouse(Mb::Left) if self.minimap.contains(s, s.mouse) => self.navigating = true,
             Mouse(Mb::Left) => if let Tool::Selector(ref mut selection) = self.current {
                 for &i in &selection.enemies {
                     if (self.level.enemies[i].pl.obj.pos - mousepos).norm() <= 16. {
@@ -578,25 +807,333 @@ impl GameState for Editor {
                         }
                     }
                 }
+                if s.mouse.y > 64. {
+                    self.marquee = Some(s.mouse_world());
+                }
+            } else if let Tool::Inserter(Insertion::Material(_)) = self.current {
+                match self.paint_tool {
+                    PaintTool::Rect | PaintTool::HollowRect | PaintTool::Line if s.mouse.y > 64. => {
+                        self.shape_start = Some(self.mouse_tile(s));
+                    }
+                    _ => (),
+                }
             }
-            Key(Comma) if !shift => self.rotation_speed -= 6.,
-            Key(Period) if !shift => self.rotation_speed += 6.,
+            _ if !shift && !self.has_selection() && s.settings.controls.matches(ctx, Action::RotateLeft, &event) => self.rotation_speed -= 6.,
+            _ if !shift && !self.has_selection() && s.settings.controls.matches(ctx, Action::RotateRight, &event) => self.rotation_speed += 6.,
             _ => (),
         }
     }
+
+    fn reload(&mut self, s: &mut State, ctx: &mut Context, files: &[String]) {
+        let config = files.iter().any(|f| f == "editor.toml");
+        if config {
+            match load_config(s) {
+                Ok((entities_bar, extra_bar, palette)) => {
+                    self.entities_bar = entities_bar;
+                    self.extra_bar = extra_bar;
+                    // Only adding materials so the ones in the history stay the same
+                    self.level.migrate(self.level.palette.clone().and(&palette));
+                }
+                Err(e) => error!("Couldn't reload editor.toml: {}", e),
+            }
+        }
+        if config || files.iter().any(|f| f.starts_with("materials/")) {
+            self.picker = MaterialPicker::new(s, &self.level.palette);
+            self.minimap_revision = None;
+        }
+        self.refresh_lighting(files.iter().any(|f| f.ends_with(".light")));
+        s.assets.preload(ctx, self.level.tile_sprites(), self.level.object_sprites());
+    }
+
+    fn get_level(&self) -> Option<&Level> {
+        Some(&self.level)
+    }
+    fn execute(&mut self, cmd: Command) -> bool {
+        self.deselect();
+        self.history.execute(&mut self.level, cmd);
+        true
+    }
 }
 
 impl Editor {
+    fn make_lighting(&self) -> Lighting {
+        Lighting::new(self.level.ambient, &self.level.decals, &self.level.palette, &self.level.grid)
+    }
+    /// Makes the lighting preview again if it's shown and the level has changed since, or if forced to
+    fn refresh_lighting(&mut self, force: bool) {
+        let revision = self.history.revision();
+        match self.lighting {
+            Some((rev, _)) if force || rev != revision => self.lighting = Some((revision, self.make_lighting())),
+            _ => (),
+        }
+    }
+    /// Makes the level darker or lighter everywhere in steps of tenths
+    fn change_ambient(&mut self, by: f32) {
+        let from = self.level.ambient;
+        let to = ((from + by) * 10.).round().clamp(0., 10.) / 10.;
+        if to != from {
+            self.execute(Command::SetAmbient{from, to});
+            info!("Ambient light is {}", to);
+        }
+    }
+    /// Clears the selection since its indices might not be valid anymore
+    fn deselect(&mut self) {
+        if let Tool::Selector(ref mut selection) = self.current {
+            *selection = Selection::default();
+        }
+    }
+    /// Stops painting with the current material if it has been taken out of the palette
+    fn forget_lost_material(&mut self) {
+        if let Tool::Inserter(Insertion::Material(mat)) = self.current {
+            if mat != Grid::EMPTY && self.level.palette.get(mat).is_none() {
+                self.current = Tool::Selector(Selection::default());
+            }
+        }
+    }
+    /// Copies the selection and the tiles under it to the clipboard as a prefab
+    fn copy(&self) {
+        if let Tool::Selector(ref sel) = self.current {
+            if let Some(prefab) = Prefab::copy(&self.level, &sel.enemies, &sel.intels, &sel.pickups, &sel.decals) {
+                let res = ClipboardContext::new().and_then(|mut cc| cc.set_contents(prefab.to_text()));
+                match res {
+                    Ok(()) => info!("Copied {}x{} tiles", prefab.width(), prefab.height()),
+                    Err(e) => error!("Couldn't copy to clipboard: {}", e),
+                }
+            }
+        }
+    }
+    /// Stamps the prefab on the clipboard with its top left corner at the mouse and selects what was pasted
+    fn paste(&mut self, s: &State) {
+        let text = match ClipboardContext::new().and_then(|mut cc| cc.get_contents()) {
+            Ok(text) => text,
+            Err(e) => return error!("Couldn't paste from clipboard: {}", e),
+        };
+        let prefab = match Prefab::from_text(&text) {
+            Ok(prefab) => prefab,
+            Err(e) => return warn!("Clipboard doesn't contain a prefab: {}", e),
+        };
+        // The mouse can be outside the grid, where only part of the prefab is pasted
+        let mouse = s.mouse_world();
+        let (x, y) = ((mouse.x / 32.).floor() as i32, (mouse.y / 32.).floor() as i32);
+
+        let palette = prefab.palette(&self.level.palette);
+        let changes = prefab.tiles(&palette, x, y).into_iter()
+            .filter_map(|(layer, x, y, mat)| TileChange::new(&self.level, layer, x, y, mat))
+            .collect();
+        let ents = prefab.entities(x, y);
+
+        let selection = Selection {
+            enemies: (0..ents.enemies.len()).map(|i| self.level.enemies.len() + i).collect(),
+            intels: (0..ents.intels.len()).map(|i| self.level.intels.len() + i).collect(),
+            pickups: (0..ents.pickups.len()).map(|i| self.level.pickups.len() + i).collect(),
+            decals: (0..ents.decals.len()).map(|i| self.level.decals.len() + i).collect(),
+            .. Default::default()
+        };
+
+        let from = self.level.palette.clone();
+        let cmds = std::iter::once(Command::SetPalette{from, to: palette})
+            .chain(std::iter::once(Command::Paint(changes)))
+            .chain(ents.enemies.into_iter().map(Entity::Enemy).map(Command::Insert))
+            .chain(ents.intels.into_iter().map(Entity::Intel).map(Command::Insert))
+            .chain(ents.pickups.into_iter().map(|(p, i)| Command::Insert(Entity::Pickup(p, i))))
+            .chain(ents.decals.into_iter().map(Entity::Decal).map(Command::Insert))
+            .collect();
+        self.history.execute(&mut self.level, Command::Batch(cmds));
+        self.current = Tool::Selector(selection);
+    }
+    fn set_paint_tool(&mut self, tool: PaintTool) {
+        self.paint_tool = tool;
+        self.shape_start = None;
+        self.paint_text.update(0, tool.name()).unwrap();
+    }
+    /// Paints on the layer of the index, or shows or hides it
+    fn choose_layer(&mut self, i: usize, toggle_visible: bool) {
+        if toggle_visible {
+            self.visible_layers[i].toggle();
+        } else {
+            self.set_layer(Layer::ALL[i]);
+        }
+    }
+    /// Stops the inserted entity rotating, turning it by the angle
+    fn stop_rotating(&mut self, angle: f32) {
+        self.rotation_speed = 0.;
+        match self.current {
+            Tool::Inserter(Insertion::Enemy{ref mut rot}) => *rot += angle,
+            Tool::Inserter(Insertion::Decal{ref mut rot, ..}) => *rot += angle,
+            _ => (),
+        }
+    }
+    /// Resizes the level with Ctrl, aligns the selection with Alt or else moves it in the direction
+    fn nudge(&mut self, dir: Vector2, ctrl: bool, shift: bool, alt: bool) {
+        let (dx, dy) = (dir.x as i32, dir.y as i32);
+        if ctrl && shift {
+            // Ctrl+Shift moves the top and left edges instead
+            self.resize(-dx, -dy, if dx != 0 { Anchor::TopRight } else { Anchor::BottomLeft });
+        } else if ctrl {
+            self.resize(dx, dy, Anchor::TopLeft);
+        } else if alt {
+            match (dx, dy) {
+                (0, -1) => self.align_selection(|min, _, p| p.y = min.y),
+                (0, 1) => self.align_selection(|_, max, p| p.y = max.y),
+                (-1, 0) => self.align_selection(|min, _, p| p.x = min.x),
+                (1, 0) => self.align_selection(|_, max, p| p.x = max.x),
+                _ => (),
+            }
+        } else if self.has_selection() {
+            let step = if shift { 32. } else { 1. };
+            if let Tool::Selector(ref selection) = self.current {
+                self.history.execute(&mut self.level, Command::Move(selection.clone(), step * dir));
+            }
+        }
+    }
+    fn set_layer(&mut self, layer: Layer) {
+        self.layer = layer;
+        self.paint_text.update(2, layer.name()).unwrap();
+    }
+    /// The tile under the mouse clamped to be inside the grid
+    fn mouse_tile(&self, s: &State) -> (u16, u16) {
+        let p = s.mouse_world();
+        let (x, y) = Grid::snap_coords(p.x.max(0.), p.y.max(0.));
+
+        (x.min(self.level.grid.width().saturating_sub(1)), y.min(self.level.grid.height().saturating_sub(1)))
+    }
+    /// The tiles of the shape being drawn from where it started to the mouse
+    fn shape_tiles(&self, s: &State) -> Option<Vec<(u16, u16)>> {
+        let start = self.shape_start?;
+        let end = self.mouse_tile(s);
+
+        Some(match self.paint_tool {
+            PaintTool::Rect => Grid::rect_tiles(start, end),
+            PaintTool::HollowRect => Grid::hollow_rect_tiles(start, end),
+            PaintTool::Line => Grid::line_tiles(start, end),
+            PaintTool::Brush | PaintTool::Fill => return None,
+        })
+    }
+    /// Paints the finished shape or fill with the current material as one undoable step
+    fn finish_shape(&mut self, s: &State) {
+        if let Tool::Inserter(Insertion::Material(mat)) = self.current {
+            let tiles = match self.paint_tool {
+                PaintTool::Fill if s.mouse.y > 64. => {
+                    let (x, y) = Grid::snap(s.mouse_world());
+                    self.level.layer(self.layer).flood_fill_tiles(x, y)
+                }
+                _ => self.shape_tiles(s).unwrap_or_default(),
+            };
+            self.shape_start = None;
+
+            let changes: Vec<_> = tiles.into_iter()
+                .filter_map(|(x, y)| TileChange::new(&self.level, self.layer, x, y, mat))
+                .collect();
+            if !changes.is_empty() {
+                self.history.execute(&mut self.level, Command::Paint(changes));
+            }
+        }
+    }
+    fn has_selection(&self) -> bool {
+        match self.current {
+            Tool::Selector(ref sel) => sel.exit || !(sel.enemies.is_empty() && sel.intels.is_empty()
+                && sel.pickups.is_empty() && sel.decals.is_empty()),
+            Tool::Inserter(_) => false,
+        }
+    }
+    /// Changes the positions and rotations of the selection as one undoable step
+    ///
+    /// The function is given the centre of the selection's bounding box.
+    fn transform_selection<F: FnMut(Point2, &mut Object)>(&mut self, mut f: F) {
+        if let Tool::Selector(ref selection) = self.current {
+            let from = history::selection_objects(&self.level, selection);
+            let (min, max) = match bounds(&from) {
+                Some(b) => b,
+                None => return,
+            };
+            let centre = Point2::from((min.coords + max.coords) / 2.);
+
+            let mut to = from.clone();
+            for obj in &mut to {
+                f(centre, obj);
+            }
+            self.history.execute(&mut self.level, Command::Transform{selection: selection.clone(), from, to});
+        }
+    }
+    /// Rotates the selection around its centre
+    fn rotate_selection(&mut self, angle: f32) {
+        let rot = Rotation2::new(angle);
+        self.transform_selection(|c, obj| {
+            obj.pos = c + rot * (obj.pos - c);
+            obj.rot += angle;
+        });
+    }
+    /// Lines up the selection along an edge of its bounding box
+    ///
+    /// The function is given the minimum and maximum corners.
+    fn align_selection<F: Fn(Point2, Point2, &mut Point2)>(&mut self, f: F) {
+        if let Tool::Selector(ref selection) = self.current {
+            if let Some((min, max)) = bounds(&history::selection_objects(&self.level, selection)) {
+                self.transform_selection(|_, obj| f(min, max, &mut obj.pos));
+            }
+        }
+    }
+    /// Grows or shrinks the level by the given amount of tiles keeping the anchored side in place
+    fn resize(&mut self, dw: i32, dh: i32, anchor: Anchor) {
+        let max = i32::from(u16::MAX);
+        let width = (i32::from(self.level.grid.width()) + dw).clamp(1, max) as u16;
+        let height = (i32::from(self.level.grid.height()) + dh).clamp(1, max) as u16;
+
+        let from = Box::new(self.level.clone());
+        let mut to = from.clone();
+        to.resize(width, height, anchor);
+        self.history.execute(&mut self.level, Command::ReplaceLevel{from, to});
+    }
+    /// Makes the command that deletes everything in the selection
+    fn delete_command(&self, selection: Selection) -> Command {
+        #[allow(clippy::unneeded_field_pattern)]
+        let Selection {
+            mut enemies,
+            mut intels,
+            mut pickups,
+            mut decals,
+            exit, moving: _,
+        } = selection;
+
+        let mut cmds = Vec::with_capacity(3);
+        if exit {
+            cmds.push(Command::SetExit{from: self.level.exit, to: None});
+        }
+        enemies.sort();
+        // Targets that are removed are no longer objectives and the rest need to be reindexed
+        let objectives = self.level.objectives.iter().filter_map(|&obj| match obj {
+            Objective::Kill(i) if enemies.contains(&i) => None,
+            Objective::Kill(i) => Some(Objective::Kill(i - enemies.iter().filter(|&&j| j < i).count())),
+            obj => Some(obj),
+        }).collect();
+        cmds.push(Command::SetObjectives{from: self.level.objectives.clone(), to: objectives});
+
+        intels.sort();
+        decals.sort();
+        pickups.sort();
+        let removed = enemies.into_iter().map(|i| (i, Entity::Enemy(self.level.enemies[i].clone())))
+            .chain(intels.into_iter().map(|i| (i, Entity::Intel(self.level.intels[i]))))
+            .chain(decals.into_iter().map(|i| (i, Entity::Decal(self.level.decals[i].clone()))))
+            .chain(pickups.into_iter().map(|i| {
+                let (p, t) = self.level.pickups[i];
+                (i, Entity::Pickup(p, t))
+            }))
+            .collect();
+        cmds.push(Command::Remove(removed));
+
+        Command::Batch(cmds)
+    }
     fn click(&mut self, s: &mut State, ctx: &mut Context) {
         let mousepos = self.mousepos(&s);
 
         if let Some(ins) = self.extra_bar.click(s.mouse) {
             self.current = Tool::Inserter(ins);
         } else if s.mouse.y <= 64. {
-            if s.mouse.x > START_X && s.mouse.x < START_X + self.level.palette.len() as f32 * 36. {
-                let i = ((s.mouse.x - START_X) / 36.) as u8;
-
+            if let Some(mat) = self.picker.click(s.mouse) {
+                let i = self.history.add_material(&mut self.level, mat);
                 self.current = Tool::Inserter(Insertion::Material(i));
+            } else if self.picker.in_label(s.mouse) {
+                self.picker.next_category();
             }
             if let Some(ins) = self.entities_bar.click(s.mouse) {
                 self.current = Tool::Inserter(ins);
@@ -609,28 +1146,18 @@ impl Editor {
                     if let Some(moved_from) = selection.moving {
                         let dist = mousepos - moved_from;
 
-                        if selection.exit {
-                            if let Some(ref mut exit) = self.level.exit {
-                                *exit += dist;
-                            }
-                        }
-                        for i in selection.enemies.iter().rev() {
-                            self.level.enemies[*i].pl.obj.pos += dist;
-                        }
-                        for i in selection.intels.iter().rev() {
-                            self.level.intels[*i] += dist;
-                        }
-                        for i in selection.decals.iter().rev() {
-                            self.level.decals[*i].obj.pos += dist;
-                        }
-                        for i in selection.pickups.iter().rev() {
-                            self.level.pickups[*i].0 += dist;
-                        }
                         selection.moving = None;
+                        self.history.execute(&mut self.level, Command::Move(selection.clone(), dist));
                     } else {
                         if !keyboard::is_mod_active(ctx, KeyMods::CTRL) {
                             *selection = Selection::default();
                         }
+                        if let Some(start) = self.marquee.take() {
+                            let rect = marquee_rect(start, s.mouse_world());
+                            if rect.w > MARQUEE_THRESHOLD || rect.h > MARQUEE_THRESHOLD {
+                                return select_in(&self.level, selection, rect);
+                            }
+                        }
                         for (i, enemy) in self.level.enemies.iter().enumerate() {
                             if (enemy.pl.obj.pos - mousepos).norm() <= 16. && !selection.enemies.contains(&i) {
                                 selection.enemies.push(i);
@@ -664,19 +1191,24 @@ impl Editor {
                     }
                 }
                 Tool::Inserter(Insertion::Exit) => {
-                    self.level.exit = Some(self.mousepos(&s));
+                    let cmd = Command::SetExit{from: self.level.exit, to: Some(mousepos)};
+                    self.history.execute(&mut self.level, cmd);
                     self.current = Tool::Selector(Selection{exit: true, .. Default::default()});
                 }
                 Tool::Inserter(Insertion::Enemy{rot}) => {
-                    self.level.enemies.push(Enemy::new(Object::with_rot(mousepos, rot)));
+                    let ent = Entity::Enemy(Enemy::new(Object::with_rot(mousepos, rot)));
+                    self.history.execute(&mut self.level, Command::Insert(ent));
                 },
                 Tool::Inserter(Insertion::Decal{spr, rot}) => {
-                    self.level.decals.push(Decal::new(Object::with_rot(mousepos, rot), spr));
+                    let ent = Entity::Decal(Decal::new(Object::with_rot(mousepos, rot), spr));
+                    self.history.execute(&mut self.level, Command::Insert(ent));
                 }
                 Tool::Inserter(Insertion::Pickup(i)) => {
-                    self.level.pickups.push((mousepos, i));
+                    self.history.execute(&mut self.level, Command::Insert(Entity::Pickup(mousepos, i)));
                 },
-                Tool::Inserter(Insertion::Intel) => self.level.intels.push(mousepos),
+                Tool::Inserter(Insertion::Intel) => {
+                    self.history.execute(&mut self.level, Command::Insert(Entity::Intel(mousepos)));
+                }
             }
         }
     }
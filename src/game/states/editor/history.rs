@@ -0,0 +1,437 @@
+use crate::{
+    util::{Point2, Vector2},
+    game::world::{Layer, Level, Palette, Objective},
+    obj::{Object, enemy::Enemy, decal::Decal},
+};
+
+use super::Selection;
+
+/// How many steps can be undone
+const HISTORY_LIMIT: usize = 256;
+
+/// Something placed in the level that can be inserted or removed
+#[derive(Debug, Clone)]
+pub enum Entity {
+    Enemy(Enemy),
+    Intel(Point2),
+    Pickup(Point2, u8),
+    Decal(Decal),
+}
+
+impl Entity {
+    fn insert(&self, level: &mut Level, i: usize) {
+        match self {
+            Entity::Enemy(e) => level.enemies.insert(i, e.clone()),
+            Entity::Intel(p) => level.intels.insert(i, *p),
+            Entity::Pickup(p, t) => level.pickups.insert(i, (*p, *t)),
+            Entity::Decal(d) => level.decals.insert(i, d.clone()),
+        }
+    }
+    fn remove(&self, level: &mut Level, i: usize) {
+        match self {
+            Entity::Enemy(_) => {level.enemies.remove(i);},
+            Entity::Intel(_) => {level.intels.remove(i);},
+            Entity::Pickup(..) => {level.pickups.remove(i);},
+            Entity::Decal(_) => {level.decals.remove(i);},
+        }
+    }
+    /// Index a new entity of this kind would get when pushed
+    fn end(&self, level: &Level) -> usize {
+        match self {
+            Entity::Enemy(_) => level.enemies.len(),
+            Entity::Intel(_) => level.intels.len(),
+            Entity::Pickup(..) => level.pickups.len(),
+            Entity::Decal(_) => level.decals.len(),
+        }
+    }
+}
+
+/// A change of one tile
+#[derive(Debug, Clone, Copy)]
+pub struct TileChange {
+    layer: Layer,
+    x: u16,
+    y: u16,
+    from: u16,
+    to: u16,
+}
+
+impl TileChange {
+    /// Makes the change of setting the tile of the layer to the material if it's in the grid and different
+    pub fn new(level: &Level, layer: Layer, x: u16, y: u16, to: u16) -> Option<Self> {
+        level.layer(layer).get(x, y).filter(|&from| from != to).map(|from| TileChange{layer, x, y, from, to})
+    }
+}
+
+/// An undoable change to the level
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// A whole paint stroke
+    Paint(Vec<TileChange>),
+    /// Entity pushed at the end of its list
+    Insert(Entity),
+    /// Entities removed from the given indices, sorted by index
+    Remove(Vec<(usize, Entity)>),
+    /// Selected entities moved by a distance
+    Move(Selection, Vector2),
+    /// Positions and rotations of the selected entities changed, in the order of `selection_objects`
+    Transform{selection: Selection, from: Vec<Object>, to: Vec<Object>},
+    SetExit{from: Option<Point2>, to: Option<Point2>},
+    SetStart{from: Option<Point2>, to: Option<Point2>},
+    SetObjectives{from: Vec<Objective>, to: Vec<Objective>},
+    SetAmbient{from: f32, to: f32},
+    /// Materials added to the palette, done before the tiles using them are painted
+    SetPalette{from: Palette, to: Palette},
+    ReplaceLevel{from: Box<Level>, to: Box<Level>},
+    /// Several commands done as one step
+    Batch(Vec<Command>),
+}
+
+impl Command {
+    pub fn apply(&self, level: &mut Level) {
+        use self::Command::*;
+        match self {
+            Paint(changes) => for c in changes {
+                level.layer_mut(c.layer).insert(c.x, c.y, c.to);
+            },
+            Insert(ent) => {
+                let i = ent.end(level);
+                ent.insert(level, i);
+            }
+            Remove(ents) => for (i, ent) in ents.iter().rev() {
+                ent.remove(level, *i);
+            },
+            Move(sel, dist) => move_selection(level, sel, *dist),
+            Transform{selection, to, ..} => set_selection_objects(level, selection, to),
+            SetExit{to, ..} => level.exit = *to,
+            SetStart{to, ..} => level.start_point = *to,
+            SetObjectives{to, ..} => level.objectives = to.clone(),
+            SetAmbient{to, ..} => level.ambient = *to,
+            SetPalette{to, ..} => level.palette = to.clone(),
+            ReplaceLevel{to, ..} => *level = (**to).clone(),
+            Batch(cmds) => for cmd in cmds {
+                cmd.apply(level);
+            },
+        }
+    }
+    pub fn revert(&self, level: &mut Level) {
+        use self::Command::*;
+        match self {
+            Paint(changes) => for c in changes.iter().rev() {
+                level.layer_mut(c.layer).insert(c.x, c.y, c.from);
+            },
+            Insert(ent) => {
+                let i = ent.end(level) - 1;
+                ent.remove(level, i);
+            }
+            Remove(ents) => for (i, ent) in ents {
+                ent.insert(level, *i);
+            },
+            Move(sel, dist) => move_selection(level, sel, -dist),
+            Transform{selection, from, ..} => set_selection_objects(level, selection, from),
+            SetExit{from, ..} => level.exit = *from,
+            SetStart{from, ..} => level.start_point = *from,
+            SetObjectives{from, ..} => level.objectives = from.clone(),
+            SetAmbient{from, ..} => level.ambient = *from,
+            SetPalette{from, ..} => level.palette = from.clone(),
+            ReplaceLevel{from, ..} => *level = (**from).clone(),
+            Batch(cmds) => for cmd in cmds.iter().rev() {
+                cmd.revert(level);
+            },
+        }
+    }
+}
+
+fn move_selection(level: &mut Level, selection: &Selection, dist: Vector2) {
+    if selection.exit {
+        if let Some(ref mut exit) = level.exit {
+            *exit += dist;
+        }
+    }
+    for &i in &selection.enemies {
+        level.enemies[i].pl.obj.pos += dist;
+    }
+    for &i in &selection.intels {
+        level.intels[i] += dist;
+    }
+    for &i in &selection.decals {
+        level.decals[i].obj.pos += dist;
+    }
+    for &i in &selection.pickups {
+        level.pickups[i].0 += dist;
+    }
+}
+
+/// The positions and rotations of all selected entities
+///
+/// Entities without a rotation get a rotation of 0.
+pub fn selection_objects(level: &Level, selection: &Selection) -> Vec<Object> {
+    let mut objs: Vec<Object> = selection.enemies.iter().map(|&i| level.enemies[i].pl.obj.clone())
+        .chain(selection.intels.iter().map(|&i| Object::new(level.intels[i])))
+        .chain(selection.decals.iter().map(|&i| level.decals[i].obj.clone()))
+        .chain(selection.pickups.iter().map(|&i| Object::new(level.pickups[i].0)))
+        .collect();
+    if selection.exit {
+        if let Some(exit) = level.exit {
+            objs.push(Object::new(exit));
+        }
+    }
+    objs
+}
+
+fn set_selection_objects(level: &mut Level, selection: &Selection, objs: &[Object]) {
+    let mut objs = objs.iter();
+
+    for &i in &selection.enemies {
+        level.enemies[i].pl.obj = objs.next().unwrap().clone();
+    }
+    for &i in &selection.intels {
+        level.intels[i] = objs.next().unwrap().pos;
+    }
+    for &i in &selection.decals {
+        level.decals[i].obj = objs.next().unwrap().clone();
+    }
+    for &i in &selection.pickups {
+        level.pickups[i].0 = objs.next().unwrap().pos;
+    }
+    if selection.exit && level.exit.is_some() {
+        level.exit = Some(objs.next().unwrap().pos);
+    }
+}
+
+/// Undo and redo stacks of the editor
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+    /// The paint stroke currently being drawn
+    stroke: Vec<TileChange>,
+    /// The palette before and after picking materials no step has used yet
+    added: Option<(Palette, Palette)>,
+    /// Goes up every time a step is done, undone or redone
+    revision: u32,
+}
+
+impl History {
+    /// Applies the command to the level and makes it undoable
+    pub fn execute(&mut self, level: &mut Level, cmd: Command) {
+        cmd.apply(level);
+        self.push(cmd);
+    }
+    fn push(&mut self, cmd: Command) {
+        self.end_stroke();
+        self.push_undo(cmd);
+    }
+    fn push_undo(&mut self, cmd: Command) {
+        // Materials picked for the step are undone along with it
+        let cmd = match self.added.take() {
+            Some((from, to)) => Command::Batch(vec![Command::SetPalette{from, to}, cmd]),
+            None => cmd,
+        };
+        self.revision = self.revision.wrapping_add(1);
+        self.undo.push(cmd);
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+    /// The index of the material in the level's palette, adding it to be undone with the next step if it isn't in it
+    pub fn add_material(&mut self, level: &mut Level, mat: &'static str) -> u16 {
+        if let Some(i) = level.palette.find(mat) {
+            return i
+        }
+        let from = self.added.take().map_or_else(|| level.palette.clone(), |(from, _)| from);
+        let i = level.palette.add(mat);
+        self.added = Some((from, level.palette.clone()));
+        i
+    }
+    /// Takes the materials that haven't been used in any step back out of the palette
+    fn drop_added(&mut self, level: &mut Level) {
+        if let Some((from, _)) = self.added.take() {
+            level.palette = from;
+        }
+    }
+    /// Paints a tile of the layer as part of the current stroke
+    pub fn paint(&mut self, level: &mut Level, layer: Layer, x: u16, y: u16, mat: u16) {
+        if let Some(change) = TileChange::new(level, layer, x, y, mat) {
+            level.layer_mut(layer).insert(x, y, mat);
+            self.stroke.push(change);
+        }
+    }
+    /// Finishes the current paint stroke so it is undone as one step
+    pub fn end_stroke(&mut self) {
+        if !self.stroke.is_empty() {
+            let stroke = std::mem::take(&mut self.stroke);
+            self.push_undo(Command::Paint(stroke));
+        }
+    }
+    /// Changes whenever the level has been changed by a finished step
+    #[inline]
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+    /// Returns whether there was anything to undo
+    pub fn undo(&mut self, level: &mut Level) -> bool {
+        self.end_stroke();
+        self.drop_added(level);
+        if let Some(cmd) = self.undo.pop() {
+            cmd.revert(level);
+            self.redo.push(cmd);
+            self.revision = self.revision.wrapping_add(1);
+            true
+        } else {
+            false
+        }
+    }
+    /// Returns whether there was anything to redo
+    pub fn redo(&mut self, level: &mut Level) -> bool {
+        self.end_stroke();
+        self.drop_added(level);
+        if let Some(cmd) = self.redo.pop() {
+            cmd.apply(level);
+            self.undo.push(cmd);
+            self.revision = self.revision.wrapping_add(1);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::Grid;
+
+    fn level() -> Level {
+        Level::new(Palette::default(), 4, 4)
+    }
+
+    #[test]
+    fn strokes_undo_as_one_step() {
+        let mut level = level();
+        let mut history = History::default();
+        let before = level.grid.get(0, 0);
+        history.paint(&mut level, Layer::Wall, 0, 0, 1);
+        history.paint(&mut level, Layer::Wall, 1, 0, 1);
+        // Painting the same again or outside the grid changes nothing
+        history.paint(&mut level, Layer::Wall, 1, 0, 1);
+        history.paint(&mut level, Layer::Wall, 9, 9, 1);
+        history.end_stroke();
+        assert_eq!(history.revision(), 1);
+
+        assert!(history.undo(&mut level));
+        assert_eq!((level.grid.get(0, 0), level.grid.get(1, 0)), (before, before));
+        assert!(!history.undo(&mut level));
+
+        assert!(history.redo(&mut level));
+        assert_eq!((level.grid.get(0, 0), level.grid.get(1, 0)), (Some(1), Some(1)));
+        assert!(!history.redo(&mut level));
+        assert_eq!(history.revision(), 3);
+    }
+
+    #[test]
+    fn redoing_ends_the_stroke_first() {
+        let mut level = level();
+        let mut history = History::default();
+        let before = level.grid.get(0, 0);
+        let start = Some(Point2::new(16., 16.));
+        history.execute(&mut level, Command::SetStart{from: None, to: start});
+        assert!(history.undo(&mut level));
+
+        // The stroke is a new step, so there's nothing left to redo
+        history.paint(&mut level, Layer::Wall, 0, 0, 1);
+        assert!(!history.redo(&mut level));
+        assert_eq!(level.start_point, None);
+
+        assert!(history.undo(&mut level));
+        assert_eq!(level.grid.get(0, 0), before);
+        assert!(!history.undo(&mut level));
+    }
+
+    #[test]
+    fn new_steps_drop_the_redos() {
+        let mut level = level();
+        let mut history = History::default();
+        let start = Some(Point2::new(16., 16.));
+        history.execute(&mut level, Command::SetStart{from: None, to: start});
+        assert!(history.undo(&mut level));
+        assert_eq!(level.start_point, None);
+
+        let cmd = Command::SetAmbient{from: level.ambient, to: 0.5};
+        history.execute(&mut level, cmd);
+        assert!(!history.redo(&mut level));
+        assert_eq!((level.start_point, level.ambient), (None, 0.5));
+    }
+
+    #[test]
+    fn removed_entities_go_back_in_place() {
+        let mut level = level();
+        let mut history = History::default();
+        let points: Vec<_> = (0..4).map(|i| Point2::new(i as f32, 0.)).collect();
+        for &p in &points {
+            history.execute(&mut level, Command::Insert(Entity::Intel(p)));
+        }
+        history.execute(&mut level, Command::Remove(vec![(0, Entity::Intel(points[0])), (2, Entity::Intel(points[2]))]));
+        assert_eq!(level.intels, vec![points[1], points[3]]);
+
+        history.undo(&mut level);
+        assert_eq!(level.intels, points);
+        history.undo(&mut level);
+        assert_eq!(level.intels, &points[..3]);
+    }
+
+    #[test]
+    fn batches_revert_backwards() {
+        let mut level = level();
+        let mut history = History::default();
+        let from = level.palette.clone();
+        let to = from.clone().and(&Palette::new(vec!["mud"]));
+        let mat = to.find("mud").unwrap();
+        let paint = TileChange::new(&level, Layer::Floor, 2, 2, mat).unwrap();
+        history.execute(&mut level, Command::Batch(vec![Command::SetPalette{from, to}, Command::Paint(vec![paint])]));
+        assert_eq!(level.floor.get(2, 2), Some(mat));
+
+        history.undo(&mut level);
+        assert_eq!(level.floor.get(2, 2), Some(Grid::EMPTY));
+        assert_eq!(level.palette.find("mud"), None);
+    }
+
+    #[test]
+    fn picked_materials_are_undone_with_the_paint() {
+        let mut level = level();
+        let mut history = History::default();
+        let mat = history.add_material(&mut level, "mud");
+        history.paint(&mut level, Layer::Floor, 2, 2, mat);
+        history.end_stroke();
+
+        history.undo(&mut level);
+        assert_eq!(level.floor.get(2, 2), Some(Grid::EMPTY));
+        assert_eq!(level.palette.find("mud"), None);
+        history.redo(&mut level);
+        assert_eq!(level.floor.get(2, 2), Some(mat));
+        assert_eq!(level.palette.find("mud"), Some(mat));
+
+        // Picking without painting leaves nothing behind once something is undone
+        history.add_material(&mut level, "sidewalk");
+        history.undo(&mut level);
+        assert_eq!(level.palette.find("sidewalk"), None);
+        assert_eq!(level.palette.find("mud"), None);
+    }
+
+    #[test]
+    fn only_so_many_steps_are_kept() {
+        let mut level = level();
+        let mut history = History::default();
+        for i in 0..HISTORY_LIMIT + 10 {
+            let cmd = Command::SetObjectives{from: level.objectives.clone(), to: vec![Objective::Kill(i)]};
+            history.execute(&mut level, cmd);
+        }
+        let mut undone = 0;
+        while history.undo(&mut level) {
+            undone += 1;
+        }
+        assert_eq!(undone, HISTORY_LIMIT);
+        assert_eq!(level.objectives, vec![Objective::Kill(9)]);
+    }
+}
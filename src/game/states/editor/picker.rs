@@ -0,0 +1,169 @@
+use crate::{
+    util::{Point2, Sstr, sstr},
+    io::tex::PosText,
+    game::{
+        State,
+        world::{Palette, available_materials, material_category, get_img},
+    },
+};
+use ggez::{
+    Context, GameResult,
+    graphics::{self, Color, Rect, DrawMode, DrawParam, Mesh},
+};
+
+use super::{START_X, YELLOW};
+
+/// How many materials fit in the picker's row
+const SLOTS: usize = 8;
+
+/// Row of materials in the editor bar that can be scrolled, searched and filtered by category
+pub struct MaterialPicker {
+    /// Every material with its category
+    materials: Vec<(Sstr, String)>,
+    categories: Vec<String>,
+    /// Index into `categories` or all materials if `None`
+    category: Option<usize>,
+    /// What the material names are filtered by
+    search: String,
+    /// Whether typing goes to the search
+    pub searching: bool,
+    /// The materials that are shown after filtering
+    shown: Vec<Sstr>,
+    scroll: usize,
+    label: PosText,
+    search_text: PosText,
+}
+
+impl MaterialPicker {
+    /// Lists the materials of the resource packs and those of the palette
+    pub fn new(s: &State, palette: &Palette) -> Self {
+        let mut names = available_materials();
+        for mat in (0..palette.len() as u16).filter_map(|i| palette.get(i)) {
+            if !names.iter().any(|m| m == mat) {
+                names.push(mat.to_owned());
+            }
+        }
+        let materials: Vec<_> = names.into_iter().map(|m| {
+            let cat = material_category(&m);
+            (sstr(m), cat)
+        }).collect();
+
+        let mut categories: Vec<String> = materials.iter().map(|(_, c)| c.clone()).collect();
+        categories.sort();
+        categories.dedup();
+
+        let mut picker = MaterialPicker {
+            materials,
+            categories,
+            category: None,
+            search: String::new(),
+            searching: false,
+            shown: Vec::new(),
+            scroll: 0,
+            label: s.assets.text(Point2::new(2., 18.)).and_text("All:"),
+            search_text: s.assets.text_sized(Point2::new(START_X, 0.), 14.).and_text(""),
+        };
+        picker.refilter();
+        picker
+    }
+    fn refilter(&mut self) {
+        let categories = &self.categories;
+        let category = self.category.map(|i| &*categories[i]);
+        let search = self.search.to_lowercase();
+
+        self.shown = self.materials.iter()
+            .filter(|(_, c)| category.is_none_or(|cat| cat == c))
+            .filter(|(m, _)| m.to_lowercase().contains(&search))
+            .map(|&(m, _)| m)
+            .collect();
+        self.scroll = self.scroll.min(self.shown.len().saturating_sub(SLOTS));
+
+        self.label.update(0, format!("{}:", category.unwrap_or("All"))).unwrap();
+        let search_text = if self.searching {
+            format!("Search: {}_", self.search)
+        } else if !self.search.is_empty() {
+            format!("Search: {}", self.search)
+        } else {
+            String::new()
+        };
+        self.search_text.update(0, search_text).unwrap();
+    }
+    /// Scrolls the row by some materials, negative being to the left
+    pub fn scroll(&mut self, amount: isize) {
+        let max = self.shown.len().saturating_sub(SLOTS) as isize;
+        self.scroll = (self.scroll as isize + amount).max(0).min(max) as usize;
+    }
+    /// Switches to the next category, going through all materials after the last one
+    pub fn next_category(&mut self) {
+        self.category = match self.category {
+            None if !self.categories.is_empty() => Some(0),
+            Some(i) if i + 1 < self.categories.len() => Some(i + 1),
+            _ => None,
+        };
+        self.scroll = 0;
+        self.refilter();
+    }
+    pub fn start_search(&mut self) {
+        self.searching = true;
+        self.refilter();
+    }
+    /// Stops typing in the search, clearing it if it's cancelled
+    pub fn end_search(&mut self, cancel: bool) {
+        self.searching = false;
+        if cancel {
+            self.search.clear();
+        }
+        self.refilter();
+    }
+    pub fn type_char(&mut self, c: char) {
+        if !c.is_control() {
+            self.search.push(c);
+            self.scroll = 0;
+            self.refilter();
+        }
+    }
+    pub fn backspace(&mut self) {
+        self.search.pop();
+        self.refilter();
+    }
+    #[inline]
+    pub fn in_label(&self, mouse: Point2) -> bool {
+        mouse.x < START_X && mouse.y >= 16. && mouse.y <= 48.
+    }
+    #[inline]
+    pub fn in_row(&self, mouse: Point2) -> bool {
+        mouse.x >= START_X && mouse.x < START_X + SLOTS as f32 * 36. && mouse.y >= 16. && mouse.y <= 48.
+    }
+    /// The material under the mouse
+    pub fn click(&self, mouse: Point2) -> Option<Sstr> {
+        if self.in_row(mouse) {
+            let i = ((mouse.x - START_X) / 36.) as usize;
+            self.shown.get(self.scroll + i).copied()
+        } else {
+            None
+        }
+    }
+    pub fn draw(&self, ctx: &mut Context, s: &State, current: Option<&str>) -> GameResult<()> {
+        for (i, &mat) in self.shown.iter().skip(self.scroll).take(SLOTS).enumerate() {
+            let x = START_X + i as f32 * 36.;
+
+            if current == Some(mat) {
+                let mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect{x: x - 1., y: 15., w: 34., h: 34.}, YELLOW)?;
+                graphics::draw(ctx, &mesh, DrawParam::default())?;
+            }
+            let img = get_img(ctx, &s.assets, mat);
+            graphics::draw(ctx, &*img, (Point2::new(x, 16.),))?;
+        }
+        // Scrollbar under the row if not everything fits
+        if self.shown.len() > SLOTS {
+            let w = SLOTS as f32 * 36.;
+            let bar_w = w * SLOTS as f32 / self.shown.len() as f32;
+            let bar_x = START_X + w * self.scroll as f32 / self.shown.len() as f32;
+            let mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect{x: bar_x, y: 50., w: bar_w, h: 3.}, Color{r: 0.8, g: 0.8, b: 0.8, a: 1.})?;
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+
+        self.label.draw_text(ctx)?;
+        self.search_text.draw_text(ctx)
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{
+    util::{Point2, RED},
+    io::{
+        tex::PosText,
+        btn::Button,
+    },
+    game::{
+        State, Content, GameState, StateSwitch, controls::Action,
+        event::{Event::{self, *}, MouseButton as Mb},
+    },
+};
+use ggez::{
+    Context, GameResult,
+    graphics::{Align, Rect, TextFragment},
+};
+
+/// Shown when content couldn't be loaded instead of crashing
+pub struct ErrorScreen {
+    title: PosText,
+    message: PosText,
+    menu_btn: Button<()>,
+}
+
+impl ErrorScreen {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(ctx: &mut Context, s: &mut State, message: &str) -> GameResult<Box<dyn GameState>> {
+        let w = s.width;
+        let title = s.assets.text_sized(Point2::new(w / 2., 16.), 32.).and_text(TextFragment::from("Couldn't load content").color(RED));
+        let mut message = s.assets.text(Point2::new(4., 150.)).and_text(message);
+        message.text.set_bounds(Point2::new(w - 8., f32::INFINITY), Align::Left);
+        let menu_btn = Button::new(ctx, &s.assets, Rect{x: 3. * w / 7., y: 64., w: w / 7., h: 64.}, "Menu", ())?;
+
+        Ok(Box::new(ErrorScreen {
+            title,
+            message,
+            menu_btn,
+        }))
+    }
+    fn menu(&self, s: &mut State) {
+        // The campaign might be the content that's broken
+        if let Content::Campaign(_) = s.content {
+            s.content = Content::None;
+        }
+        s.switch(StateSwitch::Menu);
+    }
+}
+
+impl GameState for ErrorScreen {
+    fn draw_hud(&mut self, _s: &State, ctx: &mut Context) -> GameResult<()> {
+        self.title.draw_center(ctx)?;
+        self.message.draw_text(ctx)?;
+        self.menu_btn.draw(ctx)
+    }
+    fn event_up(&mut self, s: &mut State, ctx: &mut Context, event: Event) {
+        match event {
+            _ if s.settings.controls.matches(ctx, Action::Confirm, &event) => self.menu(s),
+            Mouse(Mb::Left) if self.menu_btn.in_bounds(s.mouse) => self.menu(s),
+            _ => (),
+        }
+    }
+}
@@ -8,7 +8,9 @@ use crate::{
     game::{
         DELTA,
         State, Content, GameState, StateSwitch, world::{Statistics, Level},
-        event::{Event::{self, *}, MouseButton as Mb, KeyCode as Key}
+        controls::Action,
+        event::{Event::{self, *}, MouseButton as Mb},
+        states::win::objectives_text,
     },
 };
 use ggez::{
@@ -21,6 +23,7 @@ pub struct Lose {
     you_died: PosText,
     time_text: PosText,
     enemies_text: PosText,
+    objectives_text: PosText,
     restart_btn: Button<()>,
     edit_btn: Option<Button<()>>,
     level: Level,
@@ -32,9 +35,10 @@ impl Lose {
     pub fn new(ctx: &mut Context, s: &mut State, stats: Statistics) -> GameResult<Box<dyn GameState>> {
         let w = s.width as f32;
         let you_died = s.assets.text(Point2::new(s.width as f32/ 2., 10.)).and_text(TextFragment::from("You died!").color(RED));
-        let time_text = s.assets.text(Point2::new(4., 20.)).and_text(format!("Time: {:.0}s", stats.time as f32 * DELTA));
+        let time_text = s.assets.text(Point2::new(4., 20.)).and_text(format!("Time: {:.0}s", stats.progress.time as f32 * DELTA));
         let enemy_total = stats.level.enemies.len();
         let enemies_text = s.assets.text(Point2::new(4., 36.)).and_text(format!("Enemies killed: {} / {}", enemy_total - stats.enemies_left, enemy_total));
+        let objectives_text = objectives_text(s.assets.text(Point2::new(4., 68.)), &stats.objectives);
         let restart_btn = Button::new(ctx, &s.assets, Rect{x: 3. * w / 7., y: 64., w: w / 7., h: 64.}, "Restart", ())?;
         let edit_btn = if let Content::File(_) = s.content {
             Some(
@@ -48,6 +52,7 @@ impl Lose {
             you_died,
             time_text,
             enemies_text,
+            objectives_text,
             restart_btn,
             edit_btn,
             level: stats.level,
@@ -71,11 +76,12 @@ impl GameState for Lose {
 
         self.you_died.draw_center(ctx)?;
         self.time_text.draw_text(ctx)?;
-        self.enemies_text.draw_text(ctx)
+        self.enemies_text.draw_text(ctx)?;
+        self.objectives_text.draw_text(ctx)
     }
-    fn event_up(&mut self, s: &mut State, _ctx: &mut Context, event: Event) {
+    fn event_up(&mut self, s: &mut State, ctx: &mut Context, event: Event) {
         match event {
-            Key(Key::Return) | Key(Key::R) => self.restart(s),
+            _ if s.settings.controls.matches(ctx, Action::Confirm, &event) || s.settings.controls.matches(ctx, Action::Reload, &event) => self.restart(s),
             Mouse(Mb::Left) => {
                 if self.restart_btn.in_bounds(s.mouse) {
                     self.restart(s);
@@ -90,4 +96,7 @@ impl GameState for Lose {
         }
 
     }
+    fn get_level(&self) -> Option<&Level> {
+        Some(&self.level)
+    }
 }
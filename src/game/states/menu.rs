@@ -4,9 +4,10 @@ use crate::{
     io::{
         tex::PosText,
         btn::Button,
+        res,
     },
     game::{
-        Campaign, Content, State, GameState, StateSwitch, world::Level,
+        Campaign, Content, State, GameState, StateSwitch, world::Level, reload, controls::Action,
         event::{Event::{self, Mouse}, MouseButton}
     },
 };
@@ -20,18 +21,27 @@ pub struct Menu {
     title_txt: PosText,
     buttons: Vec<Button<Callback>>,
     corner_text: Option<PosText>,
+    mods_txt: Option<PosText>,
 }
 
 enum Callback {
     SwitchPlay(PathBuf),
     SwitchEditor,
     Campaign(PathBuf),
+    /// Goes on with the campaign that's already loaded
+    ContinueCampaign,
+    ToggleMod(PathBuf),
+    Options,
 }
 
 // ↓
 fn button_rect(w: f32, i: f32) -> Rect {
     Rect{x:3. * w / 7., y: 64. + i * 68., w:w / 7., h:64.}
 }
+// ↓ to the left
+fn mod_rect(w: f32, i: f32) -> Rect {
+    Rect{x: w / 14., y: 64. + i * 36., w: 3. * w / 14., h: 32.}
+}
 
 impl Menu {
     #[allow(clippy::new_ret_no_self)]
@@ -45,9 +55,11 @@ impl Menu {
         };
         s.mplayer.play(ctx, "music")?;
 
-        let buttons = match &mut s.content {
-            Content::Campaign(_cam) => {
-                unreachable!()
+        let mut buttons = match &mut s.content {
+            Content::Campaign(_) => {
+                vec![
+                    Button::new(ctx, &s.assets, button_rect(w, 0.), "Continue campaign", Callback::ContinueCampaign)?,
+                ]
             }
             Content::File(p) if p.extension().and_then(|s| s.to_str()) == Some("cmp") => {
                 vec![
@@ -61,23 +73,67 @@ impl Menu {
                 ]
             }
             Content::None => {
-                std::fs::read_dir("campaigns/")?
-                    .filter_map(Result::ok)
+                res::campaigns()
+                    .into_iter()
                     .enumerate()
-                    .map(|(i, d)| Button::new(
-                        ctx, &s.assets, button_rect(w, i as f32), d.file_name().to_str().unwrap(), Callback::Campaign(d.path())
-                    ))
+                    .map(|(i, p)| {
+                        let name = p.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                        Button::new(ctx, &s.assets, button_rect(w, i as f32), &name, Callback::Campaign(p))
+                    })
                     .filter_map(Result::ok)
                     .collect()
             },
         };
+        let i = buttons.len() as f32;
+        buttons.push(Button::new(ctx, &s.assets, button_rect(w, i), "Options", Callback::Options)?);
+
+        // Mods that are on are checked and listed first in priority order
+        let enabled = res::mods();
+        let mut mods = enabled.clone();
+        mods.extend(res::available_mods().into_iter().filter(|m| !enabled.contains(m)));
+        let mods_txt = if mods.is_empty() {
+            None
+        } else {
+            Some(s.assets.text(Point2::new(w / 14., 40.)).and_text("Mods:"))
+        };
+        for (i, m) in mods.into_iter().enumerate() {
+            let check = if enabled.contains(&m) { "[x]" } else { "[ ]" };
+            let name = m.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            buttons.push(Button::new(ctx, &s.assets, mod_rect(w, i as f32), &format!("{} {}", check, name), Callback::ToggleMod(m))?);
+        }
 
         Ok(Box::new(Menu {
             title_txt: s.assets.text_sized(Point2::new(w / 2., 16.), 32.).and_text("Main Menu"),
             buttons,
             corner_text,
+            mods_txt,
         }))
     }
+    /// Turns a mod on or off, giving it the lowest priority, and reloads everything it might change
+    fn toggle_mod(&mut self, ctx: &mut Context, s: &mut State, m: PathBuf) {
+        let mut mods = res::mods();
+        match mods.iter().position(|e| *e == m) {
+            Some(i) => {
+                mods.remove(i);
+            }
+            None => mods.push(m),
+        }
+        if let Err(e) = res::set_mods(&mods) {
+            error!("Couldn't load mod: {}", e);
+            return
+        }
+
+        // Both the files of the old and new mods
+        let mut files = s.watcher.files();
+        s.watcher.changes();
+        files.extend(s.watcher.files());
+        files.sort();
+        files.dedup();
+        if let Err(e) = reload(ctx, s, self, &files) {
+            error!("Couldn't reload: {}", e);
+        }
+        s.switch(StateSwitch::Menu);
+    }
 }
 
 impl GameState for Menu {
@@ -86,6 +142,9 @@ impl GameState for Menu {
         if let Some(ref txt) = self.corner_text {
             txt.draw_text(ctx)?;
         }
+        if let Some(ref txt) = self.mods_txt {
+            txt.draw_text(ctx)?;
+        }
         for button in &self.buttons {
             button.draw(ctx)?;
         }
@@ -99,26 +158,55 @@ impl GameState for Menu {
     //         _ => (),
     //     }
     // }
+    fn resize(&mut self, s: &mut State, _ctx: &mut Context) {
+        // Everything is placed by the screen's size
+        s.switch(StateSwitch::Menu);
+    }
     fn event_up(&mut self, s: &mut State, ctx: &mut Context, event: Event) {
-        if let Mouse(MouseButton::Left) = event {
-            for button in &self.buttons {
-                if button.in_bounds(s.mouse) {
-                    s.mplayer.stop(ctx, "music").unwrap();
-                    match &button.callback {
-                        Callback::Campaign(cam) => {
-                            let mut cam = Campaign::load(cam).unwrap();
-                            let lvl = cam.next_level().unwrap();
-                            s.content = Content::Campaign(cam);
-                            s.switch(StateSwitch::Play(lvl));
-                        },
-                        Callback::SwitchPlay(p) => {
-                            let lvl = Level::load(&p).unwrap();
-                            s.switch(StateSwitch::Play(lvl));
-                        },
-                        Callback::SwitchEditor => s.switch(StateSwitch::Editor(None)),
+        let button = if s.settings.controls.matches(ctx, Action::Confirm, &event) {
+            // The first button is the main choice
+            self.buttons.first()
+        } else if let Mouse(MouseButton::Left) = event {
+            self.buttons.iter().find(|b| b.in_bounds(s.mouse))
+        } else {
+            None
+        };
+        let mut toggled = None;
+        if let Some(button) = button {
+            if let Err(e) = s.mplayer.stop(ctx, "music") {
+                error!("Couldn't stop the music: {}", e);
+            }
+            match &button.callback {
+                Callback::Campaign(cam) => match Campaign::load(cam) {
+                    Ok(mut cam) => {
+                        // Loading makes sure it has levels
+                        let lvl = cam.next_level().expect("campaign has levels");
+                        s.content = Content::Campaign(cam);
+                        s.switch(StateSwitch::Play(lvl));
+                    }
+                    Err(e) => s.switch(StateSwitch::Error(e.to_string())),
+                },
+                Callback::ContinueCampaign => {
+                    let lvl = match &mut s.content {
+                        Content::Campaign(cam) => cam.next_level(),
+                        _ => None,
+                    };
+                    match lvl {
+                        Some(lvl) => s.switch(StateSwitch::Play(lvl)),
+                        None => s.switch(StateSwitch::Error("The campaign has no levels left".to_owned())),
                     }
                 }
+                Callback::SwitchPlay(p) => match Level::load(p) {
+                    Ok(lvl) => s.switch(StateSwitch::Play(lvl)),
+                    Err(e) => s.switch(StateSwitch::Error(e.to_string())),
+                },
+                Callback::SwitchEditor => s.switch(StateSwitch::Editor(None)),
+                Callback::ToggleMod(m) => toggled = Some(m.clone()),
+                Callback::Options => s.switch(StateSwitch::Options),
             }
         }
+        if let Some(m) = toggled {
+            self.toggle_mod(ctx, s, m);
+        }
     }
 }
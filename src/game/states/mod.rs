@@ -2,4 +2,6 @@ pub mod editor;
 pub mod play;
 pub mod menu;
 pub mod lose;
-pub mod win;
\ No newline at end of file
+pub mod win;
+pub mod error;
+pub mod options;
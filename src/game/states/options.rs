@@ -0,0 +1,195 @@
+use crate::{
+    util::Point2,
+    io::{
+        tex::PosText,
+        btn::Button,
+    },
+    game::{
+        State, GameState, StateSwitch,
+        settings::RESOLUTIONS,
+        camera::{MIN_ZOOM, MAX_ZOOM},
+        controls::{Action, Binding, Input},
+        event::{Event::{self, Key, Mouse}, MouseButton, KeyCode},
+    },
+};
+use ggez::{
+    Context, GameResult,
+    graphics::Rect,
+    input::keyboard,
+};
+
+/// Screen for changing the settings, which are saved when leaving it
+pub struct Options {
+    title_txt: PosText,
+    help_txt: PosText,
+    buttons: Vec<Button<Row>>,
+    /// The action waiting for an input to be bound to it, with the input of the negative end already pressed for axes
+    binding: Option<(Action, Option<Input>)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Row {
+    MasterVolume,
+    MusicVolume,
+    EffectsVolume,
+    Resolution,
+    Fullscreen,
+    Zoom,
+    FogOfWar,
+    Action(Action),
+    Back,
+}
+
+// ↓ with the settings in the left column and the bindings in the right
+fn row_rect(w: f32, column: f32, i: f32) -> Rect {
+    Rect{x: w / 2. - 410. + column * 420., y: 64. + i * 36., w: 400., h: 32.}
+}
+
+/// How much the zoom changes with each click
+const ZOOM_STEP: f32 = 0.25;
+
+/// Steps a volume by a tenth
+fn step_volume(volume: &mut f32, dir: f32) {
+    *volume = ((*volume * 10.).round() + dir).clamp(0., 10.) / 10.;
+}
+
+impl Options {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(ctx: &mut Context, s: &mut State) -> GameResult<Box<dyn GameState>> {
+        let w = s.width;
+        Ok(Box::new(Options {
+            title_txt: s.assets.text_sized(Point2::new(w / 2., 16.), 32.).and_text("Options"),
+            help_txt: s.assets.text_sized(Point2::new(4., s.height - 20.), 14.).and_text("Left click to increase or change, right click to decrease"),
+            buttons: Self::buttons(ctx, s, None)?,
+            binding: None,
+        }))
+    }
+    fn buttons(ctx: &mut Context, s: &State, binding: Option<(Action, Option<Input>)>) -> GameResult<Vec<Button<Row>>> {
+        let w = s.width;
+        let settings = &s.settings;
+        let percent = |v: f32| format!("{:.0}%", v * 100.);
+
+        let rows = vec![
+            (format!("Master volume: {}", percent(settings.master_volume)), Row::MasterVolume),
+            (format!("Music volume: {}", percent(settings.music_volume)), Row::MusicVolume),
+            (format!("Effects volume: {}", percent(settings.effects_volume)), Row::EffectsVolume),
+            (format!("Resolution: {}x{}", settings.width, settings.height), Row::Resolution),
+            (format!("Fullscreen: {}", if settings.fullscreen { "On" } else { "Off" }), Row::Fullscreen),
+            (format!("Zoom: {}", percent(settings.zoom)), Row::Zoom),
+            (format!("Fog of war: {}", if settings.fog_of_war { "On" } else { "Off" }), Row::FogOfWar),
+        ];
+        let mut buttons = Vec::with_capacity(rows.len() + Action::GAME.len() + 1);
+        for (i, (text, row)) in rows.into_iter().enumerate() {
+            buttons.push(Button::new(ctx, &s.assets, row_rect(w, 0., i as f32), &text, row)?);
+        }
+        // With a gap under the settings
+        buttons.push(Button::new(ctx, &s.assets, row_rect(w, 0., 8.), "Back", Row::Back)?);
+        for (i, &action) in Action::GAME.iter().enumerate() {
+            let inputs = match binding {
+                Some((a, None)) if a == action && action.is_axis() => "press for left or up...".to_owned(),
+                Some((a, Some(_))) if a == action => "press for right or down...".to_owned(),
+                Some((a, _)) if a == action => "press a key or button...".to_owned(),
+                _ => settings.controls.get(action).iter().map(Binding::to_string).collect::<Vec<_>>().join(", "),
+            };
+            let text = format!("{}: {}", action.name(), inputs);
+            buttons.push(Button::new(ctx, &s.assets, row_rect(w, 1., i as f32), &text, Row::Action(action))?);
+        }
+        Ok(buttons)
+    }
+    fn refresh(&mut self, ctx: &mut Context, s: &State) {
+        match Self::buttons(ctx, s, self.binding) {
+            Ok(buttons) => self.buttons = buttons,
+            Err(e) => error!("{}", e),
+        }
+    }
+    /// Changes the setting of the row, going backwards if `dir` is negative
+    fn change(&mut self, ctx: &mut Context, s: &mut State, row: Row, dir: f32) {
+        let settings = &mut s.settings;
+        match row {
+            Row::MasterVolume => step_volume(&mut settings.master_volume, dir),
+            Row::MusicVolume => step_volume(&mut settings.music_volume, dir),
+            Row::EffectsVolume => step_volume(&mut settings.effects_volume, dir),
+            Row::FogOfWar => settings.fog_of_war = !settings.fog_of_war,
+            Row::Zoom => settings.zoom = (settings.zoom + dir * ZOOM_STEP).clamp(MIN_ZOOM, MAX_ZOOM),
+            Row::Resolution | Row::Fullscreen => {
+                if let Row::Resolution = row {
+                    let cur = RESOLUTIONS.iter().position(|&r| r == (settings.width, settings.height)).unwrap_or(0);
+                    let next = if dir < 0. { cur + RESOLUTIONS.len() - 1 } else { cur + 1 } % RESOLUTIONS.len();
+                    let (w, h) = RESOLUTIONS[next];
+                    settings.width = w;
+                    settings.height = h;
+                } else {
+                    settings.fullscreen = !settings.fullscreen;
+                }
+                if let Err(e) = settings.apply_window(ctx) {
+                    error!("Couldn't change the window: {}", e);
+                }
+                s.width = settings.width;
+                s.height = settings.height;
+                // Everything is placed by the screen's size
+                s.switch(StateSwitch::Options);
+                return
+            }
+            Row::Action(action) => self.binding = Some((action, None)),
+            Row::Back => {
+                if let Err(e) = s.settings.save(ctx) {
+                    error!("Couldn't save settings: {}", e);
+                }
+                s.switch(StateSwitch::Menu);
+                return
+            }
+        }
+        s.mplayer.set_volumes(s.settings.master_volume, s.settings.music_volume, s.settings.effects_volume);
+        if let Row::EffectsVolume | Row::MasterVolume = row {
+            // Let them hear how loud it is now
+            let _ = s.mplayer.play(ctx, "hit");
+        }
+        self.refresh(ctx, s);
+    }
+}
+
+impl GameState for Options {
+    fn draw_hud(&mut self, _s: &State, ctx: &mut Context) -> GameResult<()> {
+        self.title_txt.draw_center(ctx)?;
+        self.help_txt.draw_text(ctx)?;
+        for button in &self.buttons {
+            button.draw(ctx)?;
+        }
+        Ok(())
+    }
+    fn resize(&mut self, s: &mut State, _ctx: &mut Context) {
+        s.switch(StateSwitch::Options);
+    }
+    fn event_up(&mut self, s: &mut State, ctx: &mut Context, event: Event) {
+        if let Some((action, neg)) = self.binding {
+            self.binding = match event {
+                Key(KeyCode::Escape) => None,
+                e => match (Input::from_event(&e, keyboard::active_mods(ctx)), neg) {
+                    // Axes need another input for their positive end
+                    (Some(input), None) if action.is_axis() => Some((action, Some(input))),
+                    (Some(pos), Some(neg)) => {
+                        s.settings.controls.bind(action, Binding::Axis(neg, pos));
+                        None
+                    }
+                    (Some(input), None) => {
+                        s.settings.controls.bind(action, Binding::Button(input));
+                        None
+                    }
+                    (None, _) => return,
+                }
+            };
+            self.refresh(ctx, s);
+            return
+        }
+
+        let dir = match event {
+            _ if s.settings.controls.matches(ctx, Action::Back, &event) => return self.change(ctx, s, Row::Back, 1.),
+            Mouse(MouseButton::Left) => 1.,
+            Mouse(MouseButton::Right) => -1.,
+            _ => return,
+        };
+        if let Some(row) = self.buttons.iter().find(|b| b.in_bounds(s.mouse)).map(|b| b.callback) {
+            self.change(ctx, s, row, dir);
+        }
+    }
+}
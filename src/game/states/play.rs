@@ -2,34 +2,40 @@ use std::f32::consts::FRAC_1_SQRT_2 as COS_45_D;
 use crate::{
     ext::FloatExt,
     util::{
-        BLUE, GREEN, RED,
+        BLUE, GREEN, RED, YELLOW,
         angle_to_vec, angle_from_vec,
-        ver, hor,
-        Vector2, Point2
+        Point2
     },
     io::tex::PosText,
     obj::{Object, decal::Decal, pickup::Pickup, player::{Player}, enemy::{Enemy, Chaser}, health::Health},
     game::{
-        DELTA, State, GameState, StateSwitch, world::{Level, Statistics, World},
-        event::{Event::{self, Key, Mouse}, MouseButton, KeyCode, KeyMods}
+        DELTA, State, GameState, StateSwitch, ghost::Ghost, controls::Action, minimap::Minimap, particles::{self, Particles},
+        world::{Level, Statistics, World, Fog, Lighting, Objective, Status, Progress, objectives_won, objectives_failed},
+        event::Event
     },
 };
 use ggez::{
     Context, GameResult,
     graphics::{
         self, Drawable, DrawMode, Rect,
-        Color, DrawParam,
+        Color, TextFragment,
         MeshBuilder, Mesh, WHITE,
         spritebatch::SpriteBatch,
     },
-    input::{
-        keyboard,
-        mouse,
-    },
+    input::mouse,
 };
 
 use rand::{thread_rng, prelude::SliceRandom};
 
+/// How far from the player the crosshair is when aiming with a gamepad
+const CROSSHAIR_DIST: f32 = 160.;
+/// How close the player has to be to a light to switch it
+const SWITCH_REACH: f32 = 48.;
+/// How far the knife reaches
+const KNIFE_REACH: f32 = 44.;
+/// Ticks between the dust clouds of footsteps
+const FOOTSTEP_TICKS: usize = 12;
+
 pub fn new_blood(mut obj: Object) -> Decal {
     obj.pos += 16. * angle_to_vec(obj.rot);
     Decal {
@@ -49,12 +55,28 @@ pub struct Play {
     hp_text: PosText,
     arm_text: PosText,
     status_text: PosText,
+    objectives_text: PosText,
     hud: Hud,
+    minimap: Minimap,
+    /// What the player can see if fog of war is on
+    fog: Option<Fog>,
+    lighting: Lighting,
+    particles: Particles,
     world: World,
     holes: SpriteBatch,
     cur_pickup: Option<usize>,
     victory_time: f32,
-    time: usize,
+    objectives: Vec<(Objective, Status)>,
+    progress: Progress,
+    /// Whether any enemy could see the player last tick
+    in_sight: bool,
+    /// Health and armour last tick to find out how much damage was taken
+    last_health: f32,
+    /// The run being played right now
+    recording: Ghost,
+    /// The fastest earlier run of this level
+    ghost: Option<Ghost>,
+    level_hash: u64,
     initial: Health,
     level: Level,
 }
@@ -63,29 +85,69 @@ impl Play {
     #[allow(clippy::new_ret_no_self)]
     pub fn new(ctx: &mut Context, s: &mut State, level: Level, pl: Option<(Health)>) -> GameResult<Box<dyn GameState>> {
         mouse::set_cursor_hidden(ctx, true);
+        s.assets.preload(ctx, level.tile_sprites(), level.object_sprites());
 
         let mut player = Player::from_point(level.start_point.unwrap_or_else(|| Point2::new(500., 500.)));
+        s.camera.focus_on(player.obj.pos);
+        s.camera.set_zoom(s.settings.zoom);
         if let Some(h) = pl {
             player = player.with_health(h);
         };
 
+        let mut fog = if s.settings.fog_of_war {
+            Some(Fog::new(level.grid.width(), level.grid.height()))
+        } else {
+            None
+        };
+        if let Some(fog) = &mut fog {
+            fog.update(&level.palette, &level.grid, player.obj.pos);
+        }
+
+        let lighting = Lighting::new(level.ambient, &level.decals, &level.palette, &level.grid);
+
+        let level_hash = level.content_hash();
+        let ghost = Ghost::load(ctx, level_hash).unwrap_or_else(|e| {
+            warn!("Couldn't load ghost: {}", e);
+            None
+        });
+
+        let objectives: Vec<_> = level.active_objectives().into_iter().map(|o| (o, Status::Incomplete)).collect();
+        let objectives_text = objectives.iter().fold(s.assets.text(objectives_pos(s)), |text, (o, _)| text.and_text(format!("{}\n", o)));
+
         Ok(Box::new(
             Play {
                 level: level.clone(),
                 initial: (player.health),
                 hp_text: s.assets.text(Point2::new(4., 4.)).and_text("100"),
                 arm_text: s.assets.text(Point2::new(4., 33.)).and_text("100"),
-                status_text: s.assets.text(Point2::new(s.width as f32 / 2., s.height as f32 / 2. + 32.)).and_text(""),
+                status_text: s.assets.text(status_pos(s)).and_text(""),
+                objectives_text,
                 hud: Hud::new(ctx)?,
-                time: 0,
+                minimap: Minimap::new(ctx, &level.palette, &level.floor, &level.grid, fog.as_ref())?,
+                fog,
+                lighting,
+                particles: Particles::new(),
+                objectives,
+                progress: Progress::default(),
+                in_sight: false,
+                last_health: player.health.hp + player.health.armour,
+                recording: Ghost::default(),
+                ghost,
+                level_hash,
                 victory_time: 0.,
                 cur_pickup: None,
                 world: {
+                    let mut enemies = level.enemies;
+                    for (i, enemy) in enemies.iter_mut().enumerate() {
+                        enemy.id = i;
+                    }
                     let mut world = World {
-                        enemies: level.enemies,
+                        enemies,
                         player,
                         palette: level.palette,
+                        floor: level.floor,
                         grid: level.grid,
+                        overlay: level.overlay,
                         exit: level.exit,
                         intels: level.intels,
                         decals: level.decals,
@@ -102,6 +164,46 @@ impl Play {
     }
 }
 
+// HUD text is anchored to the top right and the middle of the screen
+fn objectives_pos(s: &State) -> Point2 {
+    Point2::new(s.width - 320., 4.)
+}
+fn status_pos(s: &State) -> Point2 {
+    Point2::new(s.width / 2., s.height / 2. + 32.)
+}
+
+impl Play {
+    /// Enemies are only shown if they're chasing the player or the player can see them
+    ///
+    /// With fog of war, the rest is only shown where the player has been able to see.
+    fn map_markers(&self) -> Vec<(Point2, Color)> {
+        let player = self.world.player.obj.pos;
+        let can_see = |p: Point2| match &self.fog {
+            Some(fog) => fog.can_see(p),
+            None => self.world.grid.ray_cast(&self.world.palette, player, p - player, true).full(),
+        };
+        let seen = |p: &Point2| self.fog.as_ref().is_none_or(|fog| fog.has_seen(*p));
+
+        let mut markers: Vec<_> = self.world.enemies.iter()
+            .filter(|e| e.behaviour.chasing() || can_see(e.pl.obj.pos))
+            .map(|e| (e.pl.obj.pos, RED))
+            .collect();
+        markers.extend(self.world.intels.iter().copied().filter(seen).map(|p| (p, YELLOW)));
+        markers.extend(self.world.exit.filter(seen).map(|p| (p, BLUE)));
+        markers.push((player, GREEN));
+        markers
+    }
+    fn statistics(&self) -> Statistics {
+        Statistics{
+            level: self.level.clone(),
+            progress: self.progress,
+            enemies_left: self.world.enemies.len(),
+            health_left: self.world.player.health,
+            objectives: self.objectives.clone(),
+        }
+    }
+}
+
 impl GameState for Play {
     #[allow(clippy::cognitive_complexity)]
     fn update(&mut self, s: &mut State, ctx: &mut Context) -> GameResult<()> {
@@ -114,29 +216,41 @@ impl GameState for Play {
         for (i, &intel) in self.world.intels.iter().enumerate().rev() {
             if (intel-self.world.player.obj.pos).norm() <= 15. {
                 deads.push(i);
+                self.particles.emit(&particles::SPELL, intel, 0.);
                 s.mplayer.play(ctx, "hit")?;
             }
         }
         for i in deads {
             self.world.intels.remove(i);
+            self.progress.intels_collected += 1;
         }
         let mut deads = Vec::new();
         for (i, pickup) in self.world.pickups.iter().enumerate().rev() {
             if (pickup.pos-self.world.player.obj.pos).norm() <= 15. && pickup.apply(&mut self.world.player.health) {
                 deads.push(i);
+                self.particles.emit(&particles::SPELL, pickup.pos, 0.);
                 s.mplayer.play(ctx, "hit")?;
             }
         }
         for i in deads {
             self.world.pickups.remove(i);
+            self.progress.pickups_used += 1;
         }
         self.cur_pickup = None;
 
         // Define player velocity here already because enemies need it
-        let player_vel = Vector2::new(hor(&ctx), ver(&ctx));
+        let player_vel = s.settings.controls.movement(ctx, &s.pad);
+
+        // Enemies have to be closer to see the player in the dark
+        let light = self.lighting.level_at(self.world.player.obj.pos, &self.world.palette, &self.world.grid);
 
+        let mut in_sight = false;
         for enemy in self.world.enemies.iter_mut() {
-            if enemy.can_see(self.world.player.obj.pos, &self.world.palette, &self.world.grid) {
+            if enemy.can_see(self.world.player.obj.pos, light, &self.world.palette, &self.world.grid) {
+                in_sight = true;
+                if !enemy.behaviour.chasing() {
+                    self.progress.alerts += 1;
+                }
                 enemy.behaviour = Chaser::LastKnown{
                     pos: self.world.player.obj.pos,
                     vel: player_vel,
@@ -144,52 +258,93 @@ impl GameState for Play {
             }
             enemy.update(ctx, &mut s.mplayer)?;
         }
+        if in_sight && !self.in_sight {
+            self.progress.times_spotted += 1;
+        }
+        self.in_sight = in_sight;
+
+        let health = self.world.player.health.hp + self.world.player.health.armour;
+        if health < self.last_health {
+            self.progress.damage_taken += self.last_health - health;
+            s.camera.shake((self.last_health - health) / 50.);
+        }
+        self.last_health = health;
 
-        let speed = if !keyboard::is_mod_active(ctx, KeyMods::SHIFT) {
+        let sneaking = s.settings.controls.is_down(ctx, &s.pad, Action::Sneak);
+        let speed = if !sneaking {
             200.
         } else {
             100.
         };
         self.world.player.obj.move_on_grid(player_vel, speed, &self.world.palette, &self.world.grid);
+        // Sneaking doesn't kick up dust
+        if !sneaking && player_vel.norm() > 0. && self.progress.time.is_multiple_of(FOOTSTEP_TICKS) {
+            self.particles.emit(&particles::DUST, self.world.player.obj.pos, angle_from_vec(-player_vel));
+        }
+        self.particles.update();
+        if let Some(fog) = &mut self.fog {
+            if fog.update(&self.world.palette, &self.world.grid, self.world.player.obj.pos) {
+                self.minimap.refresh(ctx, &self.world.palette, &self.world.floor, &self.world.grid, Some(fog))?;
+            }
+        }
 
-        let game_won = match self.world.exit {
-            Some(p) => self.world.intels.is_empty() && (p - self.world.player.obj.pos).norm() < 32.,
-            None => self.world.enemies.is_empty(),
-        };
+        if self.victory_time <= 0. {
+            for (i, (obj, status)) in self.objectives.iter_mut().enumerate() {
+                *status = obj.status(&self.world, &self.progress);
+
+                let colour = match *status {
+                    Status::Incomplete => WHITE,
+                    Status::Complete => GREEN,
+                    Status::Failed => RED,
+                };
+                let text = TextFragment::from(format!("{}\n", obj.progress_text(&self.world, &self.progress)));
+                self.objectives_text.update(i, text.color(colour))?;
+            }
+            if objectives_failed(&self.objectives) {
+                s.switch(StateSwitch::Lose(Box::new(self.statistics())));
+                return Ok(())
+            }
+        }
+        let game_won = objectives_won(&self.objectives);
 
         if game_won && self.victory_time <= 0. {
             s.mplayer.play(ctx, "victory")?;
             self.victory_time += DELTA;
+
+            if self.ghost.as_ref().map(|g| self.recording.len() < g.len()).unwrap_or(true) {
+                if let Err(e) = self.recording.save(ctx, self.level_hash) {
+                    error!("Couldn't save ghost: {}", e);
+                }
+            }
         } else if self.victory_time > 0. {
             self.victory_time += DELTA;
         } else {
-            self.time += 1;
+            self.recording.record(&self.world.player.obj);
+            self.progress.time += 1;
         }
         if self.victory_time >= 2. {
-            s.switch(StateSwitch::Win(Box::new(Statistics{
-                level: self.level.clone(),
-                time: self.time,
-                enemies_left: self.world.enemies.len(),
-                health_left: self.world.player.health,
-            })));
+            s.switch(StateSwitch::Win(Box::new(self.statistics())));
         }
         Ok(())
     }
     fn logic(&mut self, s: &mut State, ctx: &mut Context) -> GameResult<()> {
-        let dist = s.mouse - s.offset - self.world.player.obj.pos;
+        // Aim with the right stick if it's being used instead of the mouse
+        let dist = s.pad.aim().unwrap_or_else(|| s.mouse_world() - self.world.player.obj.pos);
 
         self.hud.update_bars(ctx, &self.world.player)?;
 
         self.world.player.obj.rot = angle_from_vec(dist);
 
-        // Center the camera on the player
-        let p = self.world.player.obj.pos;
-        s.focus_on(p);
+        // Follow the player with the camera
+        s.camera.follow(self.world.player.obj.pos);
         Ok(())
     }
 
     fn draw(&mut self, s: &State, ctx: &mut Context) -> GameResult<()> {
-        self.world.grid.draw(&self.world.palette, ctx, &s.assets)?;
+        let view = s.view();
+        self.world.floor.draw(&self.world.palette, ctx, &s.assets, view)?;
+        self.world.grid.draw(&self.world.palette, ctx, &s.assets, view)?;
+        self.world.overlay.draw(&self.world.palette, ctx, &s.assets, view)?;
 
         self.holes.draw(ctx, Default::default())?;
 
@@ -216,23 +371,42 @@ impl GameState for Play {
             graphics::draw(ctx, &*img, drawparams)?;
         }
 
+        if let Some(ghost) = &self.ghost {
+            ghost.draw(self.progress.time, ctx, &s.assets)?;
+        }
         self.world.player.draw_player(ctx, &s.assets)?;
 
         for enemy in &self.world.enemies {
-            enemy.draw(ctx, &s.assets, WHITE)?;
+            // Enemies the player can't see are hidden
+            if self.fog.as_ref().is_none_or(|fog| fog.can_see(enemy.pl.obj.pos)) {
+                enemy.draw(ctx, &s.assets, WHITE)?;
+            }
+        }
+        self.particles.draw(ctx, &s.assets)?;
+        if let Some(fog) = &self.fog {
+            fog.draw(ctx, view)?;
         }
+        self.lighting.render(ctx)?;
 
         Ok(())
     }
     fn draw_hud(&mut self, s: &State, ctx: &mut Context) -> GameResult<()> {
+        self.lighting.draw(ctx, s.width, s.height)?;
         self.hud.draw(ctx)?;
 
         self.hp_text.draw_text(ctx)?;
         self.arm_text.draw_text(ctx)?;
         self.status_text.draw_center(ctx)?;
-        
+        self.objectives_text.draw_text(ctx)?;
+
+        self.minimap.draw(ctx, s, &self.map_markers())?;
+
+        let crosshair = match s.pad.aim() {
+            Some(dir) => s.camera.to_screen(self.world.player.obj.pos) + CROSSHAIR_DIST * dir,
+            None => s.mouse,
+        };
         let drawparams = graphics::DrawParam {
-            dest: s.mouse.into(),
+            dest: crosshair.into(),
             offset: Point2::new(0.5, 0.5).into(),
             color: RED,
             .. Default::default()
@@ -240,27 +414,40 @@ impl GameState for Play {
         let img = s.assets.get_img(ctx, "common/crosshair");
         graphics::draw(ctx, &*img, drawparams)
     }
+    fn resize(&mut self, s: &mut State, _ctx: &mut Context) {
+        self.objectives_text.pos = objectives_pos(s);
+        self.status_text.pos = status_pos(s);
+    }
     fn event_up(&mut self, s: &mut State, ctx: &mut Context, event: Event) {
-        use self::KeyCode::*;
+        let controls = &s.settings.controls;
         match event {
-            Key(G) => {
+            _ if controls.matches(ctx, Action::Map, &event) => self.minimap.toggle(),
+            _ if controls.matches(ctx, Action::Drop, &event) => {
                 warn!("Dropped nothing");
             },
-            Key(R) => {},
-            Key(F) => {},
-            Mouse(MouseButton::Left) | Key(Space) => {
+            _ if controls.matches(ctx, Action::Reload, &event) => {},
+            _ if controls.matches(ctx, Action::Use, &event) => {
+                let switched = self.lighting.switch_near(self.world.player.obj.pos, SWITCH_REACH);
+                if switched {
+                    let _ = s.mplayer.play(ctx, "hit");
+                }
+            },
+            _ if controls.matches(ctx, Action::Attack, &event) => {
                 // TODO do knives with bullets too
                 let player = &mut self.world.player;
                 let mut backstab = false;
                 let mut dead = None;
+                let mut hit = false;
 
                 for (i, enemy) in self.world.enemies.iter_mut().enumerate() {
                     let dist = player.obj.pos-enemy.pl.obj.pos;
                     let dist_len = dist.norm();
-                    if dist_len < 44. {
+                    if dist_len < KNIFE_REACH {
+                        hit = true;
                         backstab = angle_to_vec(enemy.pl.obj.rot).dot(&dist) / dist_len < COS_45_D;
 
                         self.world.decals.push(new_blood(enemy.pl.obj.clone()));
+                        self.particles.emit(&particles::BLOOD, enemy.pl.obj.pos, angle_from_vec(-dist));
                         enemy.pl.health.weapon_damage(if backstab { 165. } else { 33. }, 0.92);
                         if enemy.pl.health.is_dead() {
                             dead = Some(i);
@@ -268,19 +455,37 @@ impl GameState for Play {
                         }
                     }
                 }
+                // Swinging into a wall chips it
+                if !hit {
+                    let reach = KNIFE_REACH * angle_to_vec(player.obj.rot);
+                    let cast = self.world.grid.ray_cast(&self.world.palette, player.obj.pos, reach, true);
+                    if !cast.full() {
+                        self.particles.emit(&particles::IMPACT, cast.into_point(), angle_from_vec(-reach));
+                    }
+                }
                 if let Some(i) = dead {
-                    s.mplayer.play(ctx, "death").unwrap();
+                    if let Err(e) = s.mplayer.play(ctx, "death") {
+                        error!("Couldn't play sound: {}", e);
+                    }
+                    if backstab {
+                        self.progress.backstabs += 1;
+                    }
 
                     let Enemy{pl: Player{ obj: Object{pos, ..}, ..}, ..}
                         = self.world.enemies.remove(i);
                 }
 
-                s.mplayer.play(ctx, if backstab {"shuk"} else {"hling"}).unwrap();
+                if let Err(e) = s.mplayer.play(ctx, if backstab {"shuk"} else {"hling"}) {
+                    error!("Couldn't play sound: {}", e);
+                }
             }
             _ => (),
         }
     }
 
+    fn get_level(&self) -> Option<&Level> {
+        Some(&self.level)
+    }
     fn get_world(&self) -> Option<&World> {
         Some(&self.world)
     }
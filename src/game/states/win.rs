@@ -1,5 +1,5 @@
 use crate::{
-    util::Point2,
+    util::{Point2, GREEN, RED},
     io::{
         tex::PosText,
         btn::Button,
@@ -7,13 +7,15 @@ use crate::{
     obj::{health::Health, player},
     game::{
         DELTA,
-        State, Content, GameState, StateSwitch, world::{Level, Statistics},
-        event::{Event::{self, Key, Mouse}, MouseButton, KeyCode},
+        State, Content, GameState, StateSwitch, world::{Level, Statistics, Objective, Status},
+        score::{Run, Leaderboards},
+        controls::Action,
+        event::{Event::{self, Mouse}, MouseButton},
     }
 };
 use ggez::{
     Context, GameResult,
-    graphics::Rect,
+    graphics::{Rect, TextFragment, WHITE},
 };
 
 #[allow(clippy::large_enum_variant)]
@@ -27,12 +29,60 @@ enum WinButtons {
     }
 }
 
+/// Lists the objectives of a finished level coloured by whether they were completed
+pub fn objectives_text(text: PosText, objectives: &[(Objective, Status)]) -> PosText {
+    objectives.iter().fold(text.and_text("Objectives:\n"), |text, &(obj, status)| {
+        let (mark, colour) = match status {
+            Status::Complete => ('+', GREEN),
+            Status::Incomplete => (' ', WHITE),
+            Status::Failed => ('-', RED),
+        };
+        text.and_text(TextFragment::from(format!("{} {}\n", mark, obj)).color(colour))
+    })
+}
+
+/// How many runs of the leaderboard are shown
+const SHOWN_RUNS: usize = 5;
+
+/// Submits the run to the level's leaderboard and lists the top runs with the new one highlighted
+fn leaderboard(ctx: &mut Context, level: &Level, run: Run, text: PosText) -> PosText {
+    let mut boards = Leaderboards::load(ctx).unwrap_or_else(|e| {
+        warn!("Couldn't load leaderboards: {}", e);
+        Leaderboards::default()
+    });
+    let hash = level.content_hash();
+    let prev_best = boards.personal_best(hash);
+
+    let place = boards.submit(hash, run);
+    if place.is_some() {
+        if let Err(e) = boards.save(ctx) {
+            error!("Couldn't save leaderboards: {}", e);
+        }
+    }
+
+    let text = match prev_best {
+        Some(best) if place != Some(0) => text.and_text(format!("Personal best: {} ({})\n", best.score, best.rank())),
+        _ => text.and_text(TextFragment::from("New personal best!\n").color(GREEN)),
+    };
+
+    boards.get(hash).iter().take(SHOWN_RUNS).enumerate().fold(text.and_text("Top runs:\n"), |text, (i, r)| {
+        let line = TextFragment::from(format!("{:>2}. {}\n", i + 1, r));
+        if place == Some(i) {
+            text.and_text(line.color(GREEN))
+        } else {
+            text.and_text(line)
+        }
+    })
+}
+
 /// The state of the game
 pub struct Win {
     level_complete: PosText,
     time_text: PosText,
     enemies_text: PosText,
     health_text: PosText,
+    objectives_text: PosText,
+    score_text: PosText,
     buttons: WinButtons,
     health: Health,
     level: Level,
@@ -44,10 +94,22 @@ impl Win {
         let w = s.width as f32;
 
         let level_complete = s.assets.text(Point2::new(s.width as f32/ 2., 10.)).and_text("LEVEL COMPLETE");
-        let time_text = s.assets.text(Point2::new(4., 20.)).and_text(format!("Time: {:.1}s", stats.time as f32 * DELTA));
+        let time_text = s.assets.text(Point2::new(4., 20.)).and_text(format!("Time: {:.1}s", stats.progress.time as f32 * DELTA));
         let enemy_total = stats.level.enemies.len();
         let enemies_text = s.assets.text(Point2::new(4., 36.)).and_text(format!("Enemies killed: {} / {}", enemy_total - stats.enemies_left, enemy_total));
         let health_text = s.assets.text(Point2::new(4., 52.)).and_text(format!("Health left: {:02.0} / {:02.0}", stats.health_left.hp, stats.health_left.armour));
+        let objectives_text = objectives_text(s.assets.text(Point2::new(4., 84.)), &stats.objectives);
+
+        let p = &stats.progress;
+        let run = Run::new(&stats);
+        let score_text = s.assets.text(Point2::new(5. * w / 7., 20.))
+            .and_text(format!("Times spotted: {}\n", p.times_spotted))
+            .and_text(format!("Alerts raised: {}\n", p.alerts))
+            .and_text(format!("Backstab kills: {}\n", p.backstabs))
+            .and_text(format!("Pickups used: {}\n", p.pickups_used))
+            .and_text(format!("Damage taken: {:.0}\n\n", p.damage_taken))
+            .and_text(format!("Score: {}  Rank: {}\n\n", run.score, run.rank()));
+        let score_text = leaderboard(ctx, &stats.level, run, score_text);
 
         Ok(Box::new(Win {
             buttons: {
@@ -65,6 +127,8 @@ impl Win {
             time_text,
             enemies_text,
             health_text,
+            objectives_text,
+            score_text,
             level: stats.level,
             health: stats.health_left,
         }))
@@ -107,12 +171,13 @@ impl GameState for Win {
         self.level_complete.draw_center(ctx)?;
         self.time_text.draw_text(ctx)?;
         self.enemies_text.draw_text(ctx)?;
-        self.health_text.draw_text(ctx)
+        self.health_text.draw_text(ctx)?;
+        self.objectives_text.draw_text(ctx)?;
+        self.score_text.draw_text(ctx)
     }
-    fn event_up(&mut self, s: &mut State, _ctx: &mut Context, event: Event) {
-        use self::KeyCode::*;
+    fn event_up(&mut self, s: &mut State, ctx: &mut Context, event: Event) {
         match event {
-            Key(Return) => self.continue_play(s),
+            _ if s.settings.controls.matches(ctx, Action::Confirm, &event) => self.continue_play(s),
             Mouse(MouseButton::Left) => match &self.buttons {
                 WinButtons::FileMode{restart_btn, edit_btn} => {
                     if restart_btn.in_bounds(s.mouse) {
@@ -131,4 +196,7 @@ impl GameState for Win {
             _ => (),
         }
     }
+    fn get_level(&self) -> Option<&Level> {
+        Some(&self.level)
+    }
 }
@@ -0,0 +1,154 @@
+use std::fmt::{self, Display};
+use std::io::{BufRead, Cursor};
+use std::path::Path;
+
+use crate::{
+    io::{res, error::ContentError, snd::{MUSIC, EFFECTS}},
+    obj::pickup::PICKUPS,
+};
+use super::world::{Level, Objective, check_material, check_light, material_autotile, material_sprite};
+
+/// Something a level or the game refers to that doesn't resolve
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// A level or campaign that couldn't be loaded
+    Level(String),
+    /// A sprite without a PNG
+    Sprite(String),
+    /// A material whose sprite or `.mat` file is broken
+    Material(String, String),
+    /// A decal whose sprite is missing
+    Decal(String),
+    /// A decal whose `.light` file is broken
+    Light(String, String),
+    /// A pickup index with no pickup type
+    Pickup(u8),
+    /// A sound file that is missing
+    Sound(String),
+    /// An objective to kill an enemy index the level doesn't have
+    Enemy(usize),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Problem::*;
+        match self {
+            Level(e) => write!(f, "Couldn't load {}", e),
+            Sprite(spr) => write!(f, "Missing sprite {}", spr),
+            Material(mat, e) => write!(f, "Material {}: {}", mat, e),
+            Decal(spr) => write!(f, "Missing decal sprite {}", spr),
+            Light(spr, e) => write!(f, "Light of decal {}: {}", spr, e),
+            Pickup(i) => write!(f, "No pickup with index {}", i),
+            Sound(snd) => write!(f, "Missing sound {}", snd),
+            Enemy(id) => write!(f, "Objective to kill enemy {} who isn't in the level", id),
+        }
+    }
+}
+
+#[inline]
+fn sprite_exists(spr: &str) -> bool {
+    res::exists(&format!("{}.png", spr))
+}
+
+/// Checks a level or, if it ends in `.cmp`, every level of a campaign
+pub fn validate_path<P: AsRef<Path>>(path: P) -> Vec<Problem> {
+    let path = path.as_ref();
+    let mut problems = Vec::new();
+
+    if path.extension() == Some("cmp".as_ref()) {
+        match res::read_content(path) {
+            Ok(file) => for line in Cursor::new(file).lines() {
+                match line {
+                    Ok(line) => if !line.trim().is_empty() {
+                        validate_file(line.trim(), &mut problems);
+                    }
+                    Err(e) => problems.push(Problem::Level(ContentError::io(path.display().to_string(), e).to_string())),
+                }
+            }
+            Err(e) => problems.push(Problem::Level(ContentError::io(path.display().to_string(), e).to_string())),
+        }
+    } else {
+        validate_file(path, &mut problems);
+    }
+
+    dedup(problems)
+}
+
+fn validate_file<P: AsRef<Path>>(path: P, problems: &mut Vec<Problem>) {
+    let file = path.as_ref().display().to_string();
+    match res::read_content(&path).map_err(|e| ContentError::io(&*file, e)).and_then(|buf| Level::load_from(Cursor::new(buf), &file)) {
+        Ok(level) => problems.extend(validate_level(&level)),
+        Err(e) => problems.push(Problem::Level(e.to_string())),
+    }
+}
+
+/// Checks every material, decal and pickup of a level along with the sprites and sounds the game always uses
+pub fn validate_level(level: &Level) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    for mat in (0..level.palette.len() as u16).filter_map(|i| level.palette.get(i)) {
+        if let Err(e) = check_material(mat) {
+            problems.push(Problem::Material(mat.to_owned(), e.to_string()));
+            continue
+        }
+        let spr = material_sprite(mat);
+        if !sprite_exists(spr) {
+            problems.push(Problem::Material(mat.to_owned(), format!("missing sprite {}", spr)));
+        }
+        for spr in material_autotile(mat).map(|at| at.sprites()).unwrap_or_default() {
+            if !sprite_exists(spr) {
+                problems.push(Problem::Material(mat.to_owned(), format!("missing autotile sprite {}", spr)));
+            }
+        }
+    }
+    for decal in &level.decals {
+        if !sprite_exists(decal.spr) {
+            problems.push(Problem::Decal(decal.spr.to_owned()));
+        }
+        if let Err(e) = check_light(decal.spr) {
+            problems.push(Problem::Light(decal.spr.to_owned(), e.to_string()));
+        }
+    }
+    for &(_, i) in &level.pickups {
+        match PICKUPS.get(i as usize) {
+            Some(pickup) if !sprite_exists(pickup.spr) => problems.push(Problem::Sprite(pickup.spr.to_owned())),
+            Some(_) => (),
+            None => problems.push(Problem::Pickup(i)),
+        }
+    }
+    for obj in &level.objectives {
+        if let Objective::Kill(id) = *obj {
+            if id >= level.enemies.len() {
+                problems.push(Problem::Enemy(id));
+            }
+        }
+    }
+    for &spr in &["common/player", "common/enemy", "common/intel", "common/goal", "common/hole", "common/particle"] {
+        if !sprite_exists(spr) {
+            problems.push(Problem::Sprite(spr.to_owned()));
+        }
+    }
+    for music in &MUSIC {
+        if !res::exists(&format!("sounds/{}.ogg", music)) {
+            problems.push(Problem::Sound(format!("{}.ogg", music)));
+        }
+    }
+    for effect in &EFFECTS {
+        if !res::exists(&format!("sounds/{}.wav", effect)) {
+            problems.push(Problem::Sound(format!("{}.wav", effect)));
+        }
+    }
+
+    dedup(problems)
+}
+
+/// Removes repeats while keeping the order problems were found in
+fn dedup(problems: Vec<Problem>) -> Vec<Problem> {
+    let mut ret: Vec<Problem> = Vec::with_capacity(problems.len());
+    for problem in problems {
+        if !ret.contains(&problem) {
+            ret.push(problem);
+        }
+    }
+    ret
+}
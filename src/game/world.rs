@@ -1,34 +1,49 @@
 use crate::{
-    util::{Point2, Vector2, sstr},
-    io::tex::{Assets, },
+    util::{Point2, Vector2, Sstr, sstr},
+    io::{tex::{Assets, Sprite, SpriteSource}, error::ContentError},
     obj::{
         player::{Player},
         enemy::Enemy,
         health::Health,
-        pickup::Pickup,
+        pickup::{Pickup, PICKUPS},
         decal::{Decal, OldDecoration},
     }
 };
 use ggez::{
     Context, GameResult,
+    graphics::{self, DrawParam, Rect, spritebatch::SpriteBatch},
     error::GameError,
 };
 
 use std::path::Path;
+use std::cell::RefCell;
 use std::fs::File;
-use std::io::{Write, BufRead, BufReader};
+use std::io::{Read, Write, BufRead, BufReader};
 
 use bincode;
 
 mod material;
 pub use material::*;
+mod objective;
+pub use objective::*;
+mod prefab;
+pub use prefab::*;
+mod fog;
+pub use fog::*;
+mod light;
+pub use light::*;
+mod autotile;
+pub use autotile::*;
 
 #[derive(Debug)]
 /// All the objects in the current world
 pub struct World {
     pub player: Player,
     pub palette: Palette,
+    pub floor: Grid,
+    /// The wall layer, the only one that is collided with
     pub grid: Grid,
+    pub overlay: Grid,
     pub exit: Option<Point2>,
     pub intels: Vec<Point2>,
     pub enemies: Vec<Enemy>,
@@ -37,6 +52,12 @@ pub struct World {
 }
 
 impl World {
+    /// Makes the tiles of all layers be rebuilt when next drawn
+    pub fn invalidate(&self) {
+        self.floor.invalidate();
+        self.grid.invalidate();
+        self.overlay.invalidate();
+    }
     pub fn enemy_pickup(&mut self) {
         for enemy in &mut self.enemies {
             let mut deads = Vec::new();
@@ -69,93 +90,218 @@ impl World {
 }
 
 pub struct Statistics {
-    pub time: usize,
+    pub progress: Progress,
     pub enemies_left: usize,
     pub health_left: Health,
+    pub objectives: Vec<(Objective, Status)>,
     pub level: Level,
 }
 
 #[derive(Debug, Clone)]
 pub struct Level {
     pub palette: Palette,
+    pub floor: Grid,
+    /// The wall layer, the only one that is collided with
+    ///
+    /// Levels from before there were layers have all their tiles here.
     pub grid: Grid,
+    pub overlay: Grid,
     pub start_point: Option<Point2>,
     pub enemies: Vec<Enemy>,
     pub exit: Option<Point2>,
     pub intels: Vec<Point2>,
     pub pickups: Vec<(Point2, u8)>,
     pub decals: Vec<Decal>,
+    pub objectives: Vec<Objective>,
+    /// How lit up the level is away from lights, from 0 to 1
+    pub ambient: f32,
 }
 
 impl Level {
     pub fn new(palette: Palette, width: u16, height: u16) -> Self {
         Self {
             palette,
+            floor: Grid::empty(width, height),
             grid: Grid::new(width, height),
+            overlay: Grid::empty(width, height),
             start_point: None,
             enemies: Vec::new(),
             exit: None,
             intels: Vec::new(),
             pickups: Vec::new(),
             decals: Vec::new(),
+            objectives: Vec::new(),
+            ambient: 1.,
+        }
+    }
+    #[inline]
+    pub fn layer(&self, layer: Layer) -> &Grid {
+        match layer {
+            Layer::Floor => &self.floor,
+            Layer::Wall => &self.grid,
+            Layer::Overlay => &self.overlay,
+        }
+    }
+    #[inline]
+    pub fn layer_mut(&mut self, layer: Layer) -> &mut Grid {
+        match layer {
+            Layer::Floor => &mut self.floor,
+            Layer::Wall => &mut self.grid,
+            Layer::Overlay => &mut self.overlay,
+        }
+    }
+    /// The sprites the level's tiles are drawn with
+    pub fn tile_sprites(&self) -> Vec<Sstr> {
+        let mut sprites: Vec<Sstr> = (0..self.palette.len() as u16)
+            .filter_map(|i| self.palette.get(i))
+            .flat_map(|mat| {
+                let autotile = material_autotile(mat).map(|at| at.sprites()).unwrap_or_default();
+                std::iter::once(material_sprite(mat)).chain(autotile)
+            })
+            .collect();
+        sprites.sort();
+        sprites.dedup();
+        sprites
+    }
+    /// The sprites the level's entities are drawn with
+    pub fn object_sprites(&self) -> Vec<Sstr> {
+        let mut sprites: Vec<Sstr> = self.decals.iter().map(|d| d.spr)
+            .chain(self.pickups.iter().map(|&(_, i)| PICKUPS[i as usize].spr))
+            .collect();
+        sprites.extend_from_slice(&["common/player", "common/enemy", "common/intel", "common/goal", "common/hole", "common/particle"]);
+        sprites.sort();
+        sprites.dedup();
+        sprites
+    }
+    /// Changes the palette of the level, keeping the materials of all layers
+    pub fn migrate(&mut self, palette: Palette) {
+        let from = self.palette.clone();
+        self.palette = self.grid.migrate(&from, palette.clone());
+        self.floor.migrate(&from, palette.clone());
+        self.overlay.migrate(&from, palette);
+    }
+    /// Makes the tiles of all layers be rebuilt when next drawn
+    pub fn invalidate(&self) {
+        self.floor.invalidate();
+        self.grid.invalidate();
+        self.overlay.invalidate();
+    }
+    /// The objectives of this level, or the implicit ones if none were specified
+    pub fn active_objectives(&self) -> Vec<Objective> {
+        if self.objectives.is_empty() {
+            Objective::implicit(self.exit.is_some(), self.intels.len())
+        } else {
+            self.objectives.clone()
         }
     }
-    pub fn load<P: AsRef<Path>>(path: P) -> GameResult<Self> {
-        let mut reader = BufReader::new(File::open(path)?);
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ContentError> {
+        let file = path.as_ref().display().to_string();
+        let reader = File::open(&path).map_err(|e| ContentError::io(&*file, e))?;
+        Self::load_from(reader, &file)
+    }
+    /// Reads a level, calling it `file` in errors
+    pub fn load_from<R: Read>(reader: R, file: &str) -> Result<Self, ContentError> {
+        let mut reader = BufReader::new(reader);
         let mut ret = Level::new(Palette::default(), 0, 0);
+        let (mut has_floor, mut has_overlay) = (false, false);
 
         loop {
             let mut buf = String::with_capacity(16);
-            reader.read_line(&mut buf)?;
-            match &*buf.trim_end() {
+            if reader.read_line(&mut buf).map_err(|e| ContentError::io(file, e))? == 0 {
+                return Err(ContentError::invalid(file, "Level ends without an END section"));
+            }
+            let section = buf.trim_end();
+            let bad = |e: bincode::Error| ContentError::Section{file: file.to_owned(), section: section.to_owned(), message: e.to_string()};
+            match section {
                 "" => continue,
                 "PALETTE" => ret.palette = bincode::deserialize_from(&mut reader)
                     .map(|mats: Vec<Box<str>>| Palette::new(mats.into_iter().map(sstr).collect()))
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?,
+                    .map_err(bad)?,
                 "GRD" => ret.grid = bincode::deserialize_from(&mut reader)
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?,
+                    .map(|g: NarrowGrid| g.into())
+                    .map_err(bad)?,
+                "GRD16" => ret.grid = bincode::deserialize_from(&mut reader)
+                    .map_err(bad)?,
+                "FLOOR" | "FLOOR16" => {
+                    ret.floor = read_grid(&mut reader, section.ends_with("16")).map_err(bad)?;
+                    has_floor = true;
+                }
+                "OVERLAY" | "OVERLAY16" => {
+                    ret.overlay = read_grid(&mut reader, section.ends_with("16")).map_err(bad)?;
+                    has_overlay = true;
+                }
                 "GRID" => {
                     let (w, grid): (usize, Vec<u16>) = bincode::deserialize_from(&mut reader)
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?;
+                    .map_err(bad)?;
                     ret.grid = Grid {
-                        mats: grid.into_iter().map(|n| n as u8).collect(),
-                        width: w as u16
+                        mats: grid,
+                        width: w as u16,
+                        chunks: Default::default(),
                     }
                 }
                 "START" => ret.start_point = Some(
                     bincode::deserialize_from(&mut reader)
                         .map(|(x, y)| Point2::new(x, y))
-                        .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?
+                        .map_err(bad)?
                 ),
                 "ENEMIES" => ret.enemies = bincode::deserialize_from(&mut reader)
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?,
+                    .map_err(bad)?,
                 "POINT GOAL" => ret.exit = Some(bincode::deserialize_from(&mut reader)
                     .map(|(x, y)| Point2::new(x, y))
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?),
+                    .map_err(bad)?),
                 "INTELS" => ret.intels = bincode::deserialize_from(&mut reader)
                     .map(|l: Vec<(f32, f32)>| l.into_iter().map(|(x, y)| Point2::new(x, y)).collect())
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?,
+                    .map_err(bad)?,
                 "DECORATIONS" => ret.decals = bincode::deserialize_from(&mut reader)
                     .map(|old_decs: Vec<OldDecoration>| old_decs.into_iter().map(|od| od.renew()).collect())
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?,
+                    .map_err(bad)?,
                 "DECS" => ret.decals = bincode::deserialize_from(&mut reader)
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?,
+                    .map_err(bad)?,
                 "PICKUPS" => ret.pickups = bincode::deserialize_from(&mut reader)
                     .map(|l: Vec<((f32, f32), u8)>| l.into_iter().map(|((x, y), i)| (Point2::new(x, y), i)).collect())
-                    .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?,
+                    .map_err(bad)?,
+                "OBJECTIVES" => ret.objectives = bincode::deserialize_from(&mut reader)
+                    .map_err(bad)?,
+                "AMBIENT" => ret.ambient = bincode::deserialize_from(&mut reader)
+                    .map_err(bad)?,
                 "END" => break, 
-                _ => return Err(GameError::ResourceLoadError("Bad section".to_string()))
+                _ => return Err(ContentError::invalid(file, format!("Unknown section {}", section))),
             }
         }
+        if let Some(&(_, i)) = ret.pickups.iter().find(|&&(_, i)| i as usize >= PICKUPS.len()) {
+            return Err(ContentError::invalid(file, format!("No pickup with index {}", i)));
+        }
+        // Single layer levels only have the wall layer
+        if !has_floor {
+            ret.floor = Grid::empty(ret.grid.width(), ret.grid.height());
+        }
+        if !has_overlay {
+            ret.overlay = Grid::empty(ret.grid.width(), ret.grid.height());
+        }
 
         Ok(ret)
     }
     pub fn save<P: AsRef<Path>>(&self, path: P) -> GameResult<()> {
-        let mut file = File::create(path)?;
+        self.write_to(File::create(path)?)
+    }
+    /// A hash of the level's saved contents
+    ///
+    /// Uses FNV-1a so the hash stays the same between runs and builds.
+    pub fn content_hash(&self) -> u64 {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf).expect("writing to a vec can't fail");
 
-        writeln!(file, "GRD")?;
-        bincode::serialize_into(&mut file, &self.grid)
-            .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?;
+        buf.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(b)).wrapping_mul(0x100_0000_01b3))
+    }
+    fn write_to<W: Write>(&self, mut file: W) -> GameResult<()> {
+        write_grid(&mut file, "GRD", &self.grid)?;
+        // Empty layers are left out so single layer levels are saved like before
+        if !self.floor.is_clear() {
+            write_grid(&mut file, "\nFLOOR", &self.floor)?;
+        }
+        if !self.overlay.is_clear() {
+            write_grid(&mut file, "\nOVERLAY", &self.overlay)?;
+        }
         if let Some(start) = self.start_point {
             writeln!(file, "\nSTART")?;
             bincode::serialize_into(&mut file, &(start.x, start.y))
@@ -188,31 +334,281 @@ impl Level {
             bincode::serialize_into(&mut file, &pickups)
                 .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?;
         }
+        if !self.objectives.is_empty() {
+            writeln!(file, "\nOBJECTIVES")?;
+            bincode::serialize_into(&mut file, &self.objectives)
+                .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?;
+        }
+        // Fully lit levels are saved like before there was lighting
+        if self.ambient < 1. {
+            writeln!(file, "\nAMBIENT")?;
+            bincode::serialize_into(&mut file, &self.ambient)
+                .map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))?;
+        }
 
         writeln!(file, "\nEND")?;
         Ok(())
     }
+    /// Resizes the grid keeping the anchored side in place and moves everything along with the tiles
+    pub fn resize(&mut self, width: u16, height: u16, anchor: Anchor) {
+        let (dx, dy) = self.grid.resize(width, height, anchor, 0);
+        self.floor.resize(width, height, anchor, Grid::EMPTY);
+        self.overlay.resize(width, height, anchor, Grid::EMPTY);
+        let dist = Vector2::new(dx as f32 * 32., dy as f32 * 32.);
+
+        if let Some(ref mut start) = self.start_point {
+            *start += dist;
+        }
+        if let Some(ref mut exit) = self.exit {
+            *exit += dist;
+        }
+        for enemy in &mut self.enemies {
+            enemy.pl.obj.pos += dist;
+        }
+        for intel in &mut self.intels {
+            *intel += dist;
+        }
+        for pickup in &mut self.pickups {
+            pickup.0 += dist;
+        }
+        for decal in &mut self.decals {
+            decal.obj.pos += dist;
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Grid{
+/// How grids were saved when materials were 8-bit
+///
+/// Still used for grids that fit so the files stay the same for small palettes.
+#[derive(Debug, Serialize, Deserialize)]
+struct NarrowGrid {
     width: u16,
     mats: Vec<u8>,
 }
 
+impl NarrowGrid {
+    const EMPTY: u8 = u8::MAX;
+
+    fn new(grid: &Grid) -> Option<Self> {
+        let mats = grid.mats.iter().map(|&m| match m {
+            Grid::EMPTY => Some(Self::EMPTY),
+            m if m < u16::from(Self::EMPTY) => Some(m as u8),
+            _ => None,
+        }).collect::<Option<_>>()?;
+
+        Some(NarrowGrid {
+            width: grid.width,
+            mats,
+        })
+    }
+}
+
+impl From<NarrowGrid> for Grid {
+    fn from(NarrowGrid{width, mats}: NarrowGrid) -> Self {
+        Grid {
+            width,
+            mats: mats.into_iter().map(|m| if m == NarrowGrid::EMPTY { Grid::EMPTY } else { u16::from(m) }).collect(),
+            chunks: Default::default(),
+        }
+    }
+}
+
+fn read_grid<R: Read>(reader: R, wide: bool) -> bincode::Result<Grid> {
+    if wide {
+        bincode::deserialize_from(reader)
+    } else {
+        bincode::deserialize_from(reader).map(|g: NarrowGrid| g.into())
+    }
+}
+
+/// Writes the grid in a section of the given name, with a `16` suffix if it needs 16-bit materials
+fn write_grid<W: Write>(mut file: W, section: &str, grid: &Grid) -> GameResult<()> {
+    let res = if let Some(narrow) = NarrowGrid::new(grid) {
+        writeln!(file, "{}", section)?;
+        bincode::serialize_into(&mut file, &narrow)
+    } else {
+        writeln!(file, "{}16", section)?;
+        bincode::serialize_into(&mut file, grid)
+    };
+    res.map_err(|e| GameError::ResourceLoadError(format!("{:?}", e)))
+}
+
+/// The tile layers of a level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Floor,
+    Wall,
+    Overlay,
+}
+
+impl Layer {
+    /// All layers in the order they are drawn
+    pub const ALL: [Layer; 3] = [Layer::Floor, Layer::Wall, Layer::Overlay];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layer::Floor => "Floor",
+            Layer::Wall => "Wall",
+            Layer::Overlay => "Overlay",
+        }
+    }
+}
+
+/// Which side or corner of a grid stays in place when it is resized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Centre,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    pub fn parse(s: &str) -> Option<Self> {
+        use self::Anchor::*;
+        Some(match s {
+            "tl" | "topleft" => TopLeft,
+            "t" | "top" => Top,
+            "tr" | "topright" => TopRight,
+            "l" | "left" => Left,
+            "c" | "centre" | "center" => Centre,
+            "r" | "right" => Right,
+            "bl" | "bottomleft" => BottomLeft,
+            "b" | "bottom" => Bottom,
+            "br" | "bottomright" => BottomRight,
+            _ => return None,
+        })
+    }
+    /// How many tiles the contents move when the grid grows by the given amount
+    fn offset(self, dw: i32, dh: i32) -> (i32, i32) {
+        use self::Anchor::*;
+        let x = match self {
+            TopLeft | Left | BottomLeft => 0,
+            Top | Centre | Bottom => dw / 2,
+            TopRight | Right | BottomRight => dw,
+        };
+        let y = match self {
+            TopLeft | Top | TopRight => 0,
+            Left | Centre | Right => dh / 2,
+            BottomLeft | Bottom | BottomRight => dh,
+        };
+        (x, y)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Grid{
+    width: u16,
+    mats: Vec<u16>,
+    /// Sprite batches of the tiles, rebuilt when drawn after a change
+    #[serde(skip)]
+    chunks: RefCell<Option<Vec<Chunk>>>,
+}
+
+impl Clone for Grid {
+    /// Clones the tiles but not the sprite batches, which the clone builds when it's drawn
+    fn clone(&self) -> Self {
+        Grid {
+            width: self.width,
+            mats: self.mats.clone(),
+            chunks: RefCell::new(None),
+        }
+    }
+}
+
+/// Side length of a chunk in tiles
+const CHUNK_SIZE: u16 = 16;
+
+/// A square of tiles drawn with one sprite batch for each texture atlas page or image in it
+#[derive(Debug, Clone, Default)]
+struct Chunk {
+    /// Batches of the tiles' own sprites
+    bases: Vec<(SpriteSource, SpriteBatch)>,
+    /// Batches of sprites drawn over tiles, like the edges of neighbours
+    overlays: Vec<(SpriteSource, SpriteBatch)>,
+    dirty: bool,
+}
+
+impl Chunk {
+    fn add(batches: &mut Vec<(SpriteSource, SpriteBatch)>, ctx: &mut Context, assets: &Assets, spr: Sstr, param: DrawParam) {
+        let Sprite{source, image, src} = assets.get_sprite(ctx, spr);
+        let i = batches.iter().position(|&(s, _)| s == source).unwrap_or_else(|| {
+            batches.push((source, SpriteBatch::new(image)));
+            batches.len() - 1
+        });
+        batches[i].1.add(DrawParam{src, .. param});
+    }
+    fn build(&mut self, grid: &Grid, pal: &Palette, ctx: &mut Context, assets: &Assets, cx: u16, cy: u16) {
+        self.bases.clear();
+        self.overlays.clear();
+        self.dirty = false;
+
+        let (x0, y0) = (cx * CHUNK_SIZE, cy * CHUNK_SIZE);
+        let x1 = (x0 + CHUNK_SIZE).min(grid.width());
+        let y1 = (y0 + CHUNK_SIZE).min(grid.height());
+        let tiles = (y0..y1).flat_map(|y| (x0..x1).map(move |x| (x, y)));
+
+        for (x, y, sprites) in resolve(grid, pal, tiles) {
+            let dest = Point2::new(f32::from(x) * 32. + 16., f32::from(y) * 32. + 16.);
+            for (i, &TileSprite{spr, rot}) in sprites.iter().enumerate() {
+                let param = DrawParam {
+                    dest: dest.into(),
+                    rotation: rot,
+                    offset: Point2::new(0.5, 0.5).into(),
+                    .. Default::default()
+                };
+                let batches = if i == 0 { &mut self.bases } else { &mut self.overlays };
+                Self::add(batches, ctx, assets, spr, param);
+            }
+        }
+    }
+    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        for (_, batch) in self.bases.iter().chain(&self.overlays) {
+            graphics::draw(ctx, batch, DrawParam::default())?;
+        }
+        Ok(())
+    }
+}
+
 impl Grid {
+    /// The material of tiles where a layer has nothing
+    pub const EMPTY: u16 = u16::MAX;
+
     pub fn new(width: u16, height: u16) -> Self {
         Grid {
             width,
-            mats: vec![0; (width*height) as usize],
+            mats: vec![0; width as usize * height as usize],
+            chunks: Default::default(),
+        }
+    }
+    /// A grid with only empty tiles
+    pub fn empty(width: u16, height: u16) -> Self {
+        Grid {
+            width,
+            mats: vec![Self::EMPTY; width as usize * height as usize],
+            chunks: Default::default(),
         }
     }
+    /// Makes every chunk be rebuilt when next drawn, like when the sprites have been reloaded
+    pub fn invalidate(&self) {
+        self.chunks.replace(None);
+    }
+    /// Whether all tiles are empty
+    pub fn is_clear(&self) -> bool {
+        self.mats.iter().all(|&m| m == Self::EMPTY)
+    }
     pub fn migrate(&mut self, from: &Palette, to: Palette) -> Palette {
         let to = to.and(from);
 
-        for mat in &mut self.mats {
+        for mat in self.mats.iter_mut().filter(|m| **m != Self::EMPTY) {
             *mat = to.find(from.get(*mat).unwrap()).unwrap();
         }
+        self.chunks.replace(None);
 
         to
     }
@@ -221,46 +617,41 @@ impl Grid {
         self.width
     }
     pub fn height(&self) -> u16 {
-        self.mats.len() as u16 / self.width
-    }
-    pub fn widen(&mut self) {
-        let width = self.width as usize;
-        let height = self.height() as usize;
-        self.mats.reserve_exact(height);
-        for i in (1..=height).rev().map(|i| i * width) {
-            self.mats.insert(i, 0);
-        }
-        self.width += 1;
-    }
-    pub fn thin(&mut self) {
-        if self.width <= 1 {
-            return
-        }
-        let width = self.width;
-        for i in (1..=self.height()).rev().map(|i| i * width - 1) {
-            self.mats.remove(i as usize);
-        }
-        self.width -= 1;
-    }
-    pub fn heighten(&mut self) {
-        let new_len = self.mats.len() + self.width as usize;
-        self.mats.reserve_exact(self.width as usize);
-        self.mats.resize(new_len, 0);
+        (self.mats.len() / self.width as usize) as u16
     }
-    pub fn shorten(&mut self) {
-        let new_len = self.mats.len() - self.width as usize;
-        if new_len == 0 {
-            return
+    /// Resizes the grid keeping the anchored side in place
+    ///
+    /// New tiles get the given material. Returns how many tiles the old contents were moved.
+    pub fn resize(&mut self, width: u16, height: u16, anchor: Anchor, fill: u16) -> (i32, i32) {
+        let (width, height) = (width.max(1), height.max(1));
+        let (dx, dy) = anchor.offset(
+            i32::from(width) - i32::from(self.width),
+            i32::from(height) - i32::from(self.height()),
+        );
+
+        let mut mats = vec![fill; width as usize * height as usize];
+        for y in 0..height {
+            for x in 0..width {
+                let (ox, oy) = (i32::from(x) - dx, i32::from(y) - dy);
+                if ox >= 0 && oy >= 0 && ox < i32::from(self.width) && oy < i32::from(self.height()) {
+                    mats[x as usize + y as usize * width as usize] = self.mats[self.idx(ox as u16, oy as u16)];
+                }
+            }
         }
-        self.mats.truncate(new_len);
+        self.width = width;
+        self.mats = mats;
+        self.chunks.replace(None);
+
+        (dx, dy)
     }
     #[inline]
     pub fn snap(c: Point2) -> (u16, u16) {
         Self::snap_coords(c.x, c.y)
     }
+    /// Done in `usize` since a grid can have more tiles than fit in a `u16`
     #[inline]
     fn idx(&self, x: u16, y: u16) -> usize {
-        x.saturating_add(y.saturating_mul(self.width)) as usize
+        x as usize + y as usize * self.width as usize
     }
     pub fn snap_coords(x: f32, y: f32) -> (u16, u16) {
         fn db32omin(n: f32) -> u16 {
@@ -273,7 +664,7 @@ impl Grid {
 
         (db32omin(x), db32omin(y))
     }
-    pub fn get(&self, x: u16, y: u16) -> Option<u8> {
+    pub fn get(&self, x: u16, y: u16) -> Option<u16> {
         if x < self.width {
             self.mats.get(self.idx(x, y)).cloned()
         } else {
@@ -287,14 +678,88 @@ impl Grid {
     pub fn is_solid(&self, pal: &Palette, x: u16, y: u16) -> bool {
         self.get(x, y).map(|m| pal.is_solid(m)).unwrap_or(true)
     }
-    pub fn insert(&mut self, x: u16, y: u16, mat: u8) {
+    pub fn insert(&mut self, x: u16, y: u16, mat: u16) {
         if x < self.width {
             let i = self.idx(x, y);
             if let Some(m) = self.mats.get_mut(i) {
-                *m = mat;
+                if *m != mat {
+                    *m = mat;
+                    self.mark_dirty(x, y);
+                }
             }
         }
     }
+    /// All tiles in the rectangle spanned by two corner tiles
+    pub fn rect_tiles((x0, y0): (u16, u16), (x1, y1): (u16, u16)) -> Vec<(u16, u16)> {
+        let (x0, x1) = (x0.min(x1), x0.max(x1));
+        let (y0, y1) = (y0.min(y1), y0.max(y1));
+
+        (y0..=y1).flat_map(|y| (x0..=x1).map(move |x| (x, y))).collect()
+    }
+    /// The outline of the rectangle spanned by two corner tiles
+    pub fn hollow_rect_tiles((x0, y0): (u16, u16), (x1, y1): (u16, u16)) -> Vec<(u16, u16)> {
+        let (x0, x1) = (x0.min(x1), x0.max(x1));
+        let (y0, y1) = (y0.min(y1), y0.max(y1));
+
+        Self::rect_tiles((x0, y0), (x1, y1)).into_iter()
+            .filter(|&(x, y)| x == x0 || x == x1 || y == y0 || y == y1)
+            .collect()
+    }
+    /// The tiles on a line between two tiles using Bresenham's algorithm
+    pub fn line_tiles((x0, y0): (u16, u16), (x1, y1): (u16, u16)) -> Vec<(u16, u16)> {
+        let (mut x, mut y) = (i32::from(x0), i32::from(y0));
+        let (x1, y1) = (i32::from(x1), i32::from(y1));
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let sx = if x < x1 { 1 } else { -1 };
+        let sy = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+
+        let mut tiles = Vec::with_capacity((dx - dy) as usize + 1);
+        loop {
+            tiles.push((x as u16, y as u16));
+            if x == x1 && y == y1 {
+                break tiles
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+    /// All tiles connected to the given tile that have the same material
+    pub fn flood_fill_tiles(&self, x: u16, y: u16) -> Vec<(u16, u16)> {
+        let mat = match self.get(x, y) {
+            Some(mat) => mat,
+            None => return Vec::new(),
+        };
+        let mut visited = vec![false; self.mats.len()];
+        let mut tiles = Vec::new();
+        let mut stack = vec![(x, y)];
+
+        while let Some((x, y)) = stack.pop() {
+            if self.get(x, y) != Some(mat) || visited[self.idx(x, y)] {
+                continue
+            }
+            visited[self.idx(x, y)] = true;
+            tiles.push((x, y));
+
+            if x > 0 {
+                stack.push((x - 1, y));
+            }
+            if y > 0 {
+                stack.push((x, y - 1));
+            }
+            stack.push((x + 1, y));
+            stack.push((x, y + 1));
+        }
+        tiles
+    }
     pub fn ray_cast(&self, pal: &Palette, from: Point2, dist: Vector2, finite: bool) -> RayCast {
         let dest = from + dist;
 
@@ -394,12 +859,39 @@ impl Grid {
     pub fn dist_line_circle(line_start: Point2, line_dist: Vector2, circle_center: Point2) -> f32 {
         Self::distance_line_circle(line_start, line_dist, circle_center).norm()
     }
-    pub fn draw(&self, pal: &Palette, ctx: &mut Context, assets: &Assets) -> GameResult<()> {
-        for (i, &mat) in self.mats.iter().enumerate() {
-            let x = f32::from(i as u16 % self.width) * 32.;
-            let y = f32::from(i as u16 / self.width) * 32.;
+    #[inline]
+    fn chunks_wide(&self) -> usize {
+        (self.width as usize).div_ceil(CHUNK_SIZE as usize)
+    }
+    /// Makes the chunks around a changed tile be rebuilt, since autotiling depends on the neighbours
+    fn mark_dirty(&self, x: u16, y: u16) {
+        if let Some(ref mut chunks) = *self.chunks.borrow_mut() {
+            let cw = self.chunks_wide();
+            for (x, y) in [(x.saturating_sub(1), y.saturating_sub(1)), (x + 1, y.saturating_sub(1)), (x.saturating_sub(1), y + 1), (x + 1, y + 1)].iter() {
+                let i = (x / CHUNK_SIZE) as usize;
+                let i = i.min(cw - 1) + (y / CHUNK_SIZE) as usize * cw;
+                if let Some(chunk) = chunks.get_mut(i) {
+                    chunk.dirty = true;
+                }
+            }
+        }
+    }
+    /// Draws the chunks that are in view, rebuilding those that have changed
+    pub fn draw(&self, pal: &Palette, ctx: &mut Context, assets: &Assets, view: Rect) -> GameResult<()> {
+        let (cw, ch) = (self.chunks_wide(), (self.height() as usize).div_ceil(CHUNK_SIZE as usize));
+        let mut chunks = self.chunks.borrow_mut();
+        let chunks = chunks.get_or_insert_with(|| vec![Chunk{dirty: true, .. Default::default()}; cw * ch]);
 
-            pal.draw_mat(mat, ctx, assets, x, y, Default::default())?;
+        let size = f32::from(CHUNK_SIZE) * 32.;
+        for (i, chunk) in chunks.iter_mut().enumerate() {
+            let (cx, cy) = ((i % cw) as u16, (i / cw) as u16);
+            if !view.overlaps(&Rect::new(f32::from(cx) * size, f32::from(cy) * size, size, size)) {
+                continue
+            }
+            if chunk.dirty {
+                chunk.build(self, pal, ctx, assets, cx, cy);
+            }
+            chunk.draw(ctx)?;
         }
         Ok(())
     }
@@ -499,4 +991,77 @@ impl RayCast {
         let Self{clip, ..} = self;
         clip
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::Object;
+
+    /// A grid whose tiles are numbered by their index
+    fn numbered(width: u16, height: u16) -> Grid {
+        let mut grid = Grid::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                grid.insert(x, y, x + y * width);
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn grow_top_left() {
+        let mut grid = numbered(2, 2);
+        assert_eq!(grid.resize(4, 3, Anchor::TopLeft, 9), (0, 0));
+        assert_eq!((grid.width(), grid.height()), (4, 3));
+        assert_eq!(grid.mats, vec![0, 1, 9, 9, 2, 3, 9, 9, 9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn grow_bottom_right() {
+        let mut grid = numbered(2, 2);
+        assert_eq!(grid.resize(3, 3, Anchor::BottomRight, 9), (1, 1));
+        assert_eq!(grid.mats, vec![9, 9, 9, 9, 0, 1, 9, 2, 3]);
+    }
+
+    #[test]
+    fn shrink_centre() {
+        let mut grid = numbered(4, 4);
+        assert_eq!(grid.resize(2, 2, Anchor::Centre, 9), (-1, -1));
+        assert_eq!(grid.mats, vec![5, 6, 9, 10]);
+    }
+
+    #[test]
+    fn resize_keeps_at_least_a_tile() {
+        let mut grid = numbered(2, 2);
+        grid.resize(0, 0, Anchor::TopLeft, 9);
+        assert_eq!((grid.width(), grid.height()), (1, 1));
+        assert_eq!(grid.get(0, 0), Some(0));
+    }
+
+    #[test]
+    fn more_tiles_than_fit_in_u16() {
+        let mut grid = Grid::new(10, 10);
+        grid.resize(300, 300, Anchor::TopLeft, 0);
+        assert_eq!((grid.width(), grid.height()), (300, 300));
+
+        grid.insert(299, 299, 7);
+        assert_eq!(grid.get(299, 299), Some(7));
+        assert_eq!(grid.get(299, 300), None);
+        assert_eq!(grid.get(0, u16::MAX), None);
+    }
+
+    #[test]
+    fn level_resize_moves_entities() {
+        let mut level = Level::new(Palette::default(), 4, 4);
+        level.start_point = Some(Point2::new(16., 16.));
+        level.intels.push(Point2::new(48., 48.));
+        level.enemies.push(Enemy::new(Object::new(Point2::new(80., 16.))));
+
+        level.resize(6, 6, Anchor::BottomRight);
+        assert_eq!(level.floor.width(), 6);
+        assert_eq!(level.overlay.height(), 6);
+        assert_eq!(level.start_point, Some(Point2::new(80., 80.)));
+        assert_eq!(level.intels, vec![Point2::new(112., 112.)]);
+        assert_eq!(level.enemies[0].pl.obj.pos, Point2::new(144., 80.));
+    }
+}
@@ -0,0 +1,218 @@
+use crate::util::{Sstr, sstr};
+
+use std::f32::consts::FRAC_PI_2;
+
+use super::{Grid, Palette, material_autotile, material_sprite};
+
+/// Rules for picking a material's sprite from its neighbours, declared in its `.mat` file
+///
+/// ```toml
+/// [autotile]
+/// connects = ["wood_wall"]
+/// straight = "materials/wall_straight"
+/// corner = "materials/wall_corner"
+/// edge = "materials/wall_edge"
+/// blends_over = ["grass"]
+/// ```
+///
+/// The shape sprites are drawn for how the tile joins up with the same material (or those it `connects` to)
+/// on its four sides, turned to fit. They are drawn for `end` joining downwards, `straight` up and down,
+/// `corner` right and down, `tee` right, down and left and `cross` all ways. Shapes without a sprite use the plain one.
+///
+/// The `edge` sprite is drawn on neighbouring tiles of the `blends_over` materials, drawn for a neighbour below
+/// this tile, and `outer_corner` on diagonal ones, drawn for a neighbour below and to the right.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Autotile {
+    #[serde(default)]
+    connects: Vec<String>,
+    end: Option<String>,
+    straight: Option<String>,
+    corner: Option<String>,
+    tee: Option<String>,
+    cross: Option<String>,
+    edge: Option<String>,
+    outer_corner: Option<String>,
+    #[serde(default)]
+    blends_over: Vec<String>,
+}
+
+/// A sprite drawn on a tile and how much it's turned clockwise
+#[derive(Debug, Clone, Copy)]
+pub struct TileSprite {
+    pub spr: Sstr,
+    pub rot: f32,
+}
+
+/// Neighbour offsets in the order of the bits of a neighbour mask, going clockwise from up
+const SIDES: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+/// Diagonal offsets going clockwise from up and left
+const DIAGONALS: [(i32, i32); 4] = [(-1, -1), (1, -1), (1, 1), (-1, 1)];
+
+/// Turns a neighbour mask a quarter clockwise
+#[inline]
+fn turn(mask: u8) -> u8 {
+    ((mask << 1) | (mask >> 3)) & 0b1111
+}
+
+impl Autotile {
+    /// The shape sprite and how many quarter turns it needs for a neighbour mask
+    fn shape(&self, mask: u8) -> Option<(&str, u8)> {
+        let shapes = [
+            (&self.end, 0b0100),
+            (&self.straight, 0b0101),
+            (&self.corner, 0b0110),
+            (&self.tee, 0b1110),
+            (&self.cross, 0b1111),
+        ];
+        shapes.iter().find_map(|&(spr, canonical)| {
+            let spr = spr.as_ref()?;
+            let mut m = canonical;
+            for turns in 0..4 {
+                if m == mask {
+                    return Some((&**spr, turns))
+                }
+                m = turn(m);
+            }
+            None
+        })
+    }
+    /// All the sprites named by the rules
+    pub fn sprites(&self) -> Vec<Sstr> {
+        [&self.end, &self.straight, &self.corner, &self.tee, &self.cross, &self.edge, &self.outer_corner].iter()
+            .filter_map(|spr| spr.as_ref())
+            .map(|spr| sstr(&**spr))
+            .collect()
+    }
+    fn joins(&self, mat: &str, other: &str) -> bool {
+        mat == other || self.connects.iter().any(|c| c == other)
+    }
+    fn blends_over(&self, other: &str) -> bool {
+        self.blends_over.iter().any(|m| m == other)
+    }
+}
+
+/// Works out the sprites of the given tiles from the autotiling rules of the grid's materials
+///
+/// The first sprite of a tile is its own, the rest are drawn over it.
+pub fn resolve<I: IntoIterator<Item=(u16, u16)>>(grid: &Grid, pal: &Palette, tiles: I) -> Vec<(u16, u16, Vec<TileSprite>)> {
+    let rules: Vec<Option<Autotile>> = (0..pal.len() as u16)
+        .map(|i| pal.get(i).and_then(material_autotile))
+        .collect();
+    let name = |x: i32, y: i32| -> Option<&str> {
+        if x < 0 || y < 0 {
+            return None
+        }
+        grid.get(x as u16, y as u16).and_then(|m| pal.get(m))
+    };
+    let rule = |x: i32, y: i32| -> Option<(&str, &Autotile)> {
+        if x < 0 || y < 0 {
+            return None
+        }
+        let m = grid.get(x as u16, y as u16)?;
+        Some((pal.get(m)?, rules.get(m as usize)?.as_ref()?))
+    };
+
+    tiles.into_iter().map(|(tx, ty)| {
+        let (x, y) = (i32::from(tx), i32::from(ty));
+        let mut sprites = Vec::new();
+        let mat = match name(x, y) {
+            Some(mat) => mat,
+            None => return (tx, ty, sprites),
+        };
+
+        let shape = rule(x, y).and_then(|(_, at)| {
+            let mask = SIDES.iter().enumerate()
+                .filter(|&(_, &(dx, dy))| name(x + dx, y + dy).is_some_and(|n| at.joins(mat, n)))
+                .fold(0, |mask, (i, _)| mask | 1 << i);
+            at.shape(mask)
+        });
+        sprites.push(match shape {
+            Some((spr, turns)) => TileSprite{spr: sstr(spr), rot: f32::from(turns) * FRAC_PI_2},
+            None => TileSprite{spr: material_sprite(mat), rot: 0.},
+        });
+
+        // Neighbours blending over this tile, the edge being drawn for one below so it's turned to face them
+        for (i, &(dx, dy)) in SIDES.iter().enumerate() {
+            if let Some((_, at)) = rule(x + dx, y + dy).filter(|(n, at)| *n != mat && at.blends_over(mat)) {
+                if let Some(ref edge) = at.edge {
+                    sprites.push(TileSprite{spr: sstr(&**edge), rot: (i as f32 + 2.) * FRAC_PI_2});
+                }
+            }
+        }
+        for (i, &(dx, dy)) in DIAGONALS.iter().enumerate() {
+            if let Some((n, at)) = rule(x + dx, y + dy).filter(|(n, at)| *n != mat && at.blends_over(mat)) {
+                // Only the corner if the sides next to it don't already have edges
+                if name(x + dx, y) == Some(n) || name(x, y + dy) == Some(n) {
+                    continue
+                }
+                if let Some(ref corner) = at.outer_corner {
+                    sprites.push(TileSprite{spr: sstr(&**corner), rot: (i as f32 + 2.) * FRAC_PI_2});
+                }
+            }
+        }
+        (tx, ty, sprites)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// A grid of dirt with the given tiles set to the palette's materials
+    fn grid(tiles: &[(u16, u16, u16)]) -> (Grid, Palette) {
+        let pal = Palette::new(vec!["dirt", "wall", "grass"]);
+        let mut grid = Grid::new(3, 3);
+        for &(x, y, m) in tiles {
+            grid.insert(x, y, m);
+        }
+        (grid, pal)
+    }
+
+    fn sprites_at(grid: &Grid, pal: &Palette, x: u16, y: u16) -> Vec<(Sstr, f32)> {
+        resolve(grid, pal, Some((x, y)))[0].2.iter().map(|s| (s.spr, s.rot)).collect()
+    }
+
+    #[test]
+    fn turns() {
+        assert_eq!(turn(0b0001), 0b0010);
+        assert_eq!(turn(0b1000), 0b0001);
+        assert_eq!(turn(turn(turn(turn(0b0110)))), 0b0110);
+    }
+
+    #[test]
+    fn wall_shapes() {
+        let (grid, pal) = grid(&[(1, 0, 1), (1, 1, 1), (1, 2, 1), (2, 1, 1)]);
+
+        // Joins up, right and down, the tee joining right, down and left turned three quarters
+        let sprites = sprites_at(&grid, &pal, 1, 1);
+        assert_eq!(sprites.len(), 1);
+        assert_eq!(sprites[0].0, "materials/autotile/wall_tee");
+        assert!((sprites[0].1 - 1.5 * PI).abs() < 1e-5);
+
+        assert_eq!(sprites_at(&grid, &pal, 1, 0), vec![("materials/autotile/wall_end", 0.)]);
+        let (spr, rot) = sprites_at(&grid, &pal, 1, 2)[0];
+        assert_eq!(spr, "materials/autotile/wall_end");
+        assert!((rot - PI).abs() < 1e-5);
+        assert_eq!(sprites_at(&grid, &pal, 0, 0), vec![("materials/dirt", 0.)]);
+    }
+
+    #[test]
+    fn grass_blends_over_dirt() {
+        let (grid, pal) = grid(&[(1, 0, 2)]);
+
+        // The edge is drawn for grass below, so grass above is turned around
+        let below = sprites_at(&grid, &pal, 1, 1);
+        assert_eq!(below.len(), 2);
+        assert_eq!(below[1].0, "materials/autotile/grass_edge");
+        assert!((below[1].1 - PI).abs() < 1e-5);
+
+        let diagonal = sprites_at(&grid, &pal, 2, 1);
+        assert_eq!(diagonal.len(), 2);
+        assert_eq!(diagonal[1].0, "materials/autotile/grass_corner");
+
+        // Grass doesn't blend over itself or walls
+        assert_eq!(sprites_at(&grid, &pal, 1, 0).len(), 1);
+        assert_eq!(sprites_at(&grid, &pal, 1, 2).len(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::util::Point2;
+use ggez::{
+    Context, GameResult,
+    graphics::{self, Color, Rect, DrawMode, DrawParam, MeshBuilder},
+};
+
+use super::{Grid, Palette};
+
+/// How many tiles away the player can see
+const SIGHT_RANGE: u16 = 20;
+
+/// Covers what has been seen before but can't be seen now
+const REMEMBERED: Color = Color{r: 0., g: 0., b: 0., a: 0.55};
+/// Covers what hasn't been seen yet
+const UNSEEN: Color = Color{r: 0., g: 0., b: 0., a: 1.};
+
+/// The tiles the player can see and those they have seen before
+#[derive(Debug, Clone)]
+pub struct Fog {
+    width: u16,
+    height: u16,
+    visible: Vec<bool>,
+    explored: Vec<bool>,
+    /// The tile it was last seen from
+    from: Option<(u16, u16)>,
+}
+
+impl Fog {
+    pub fn new(width: u16, height: u16) -> Self {
+        let len = width as usize * height as usize;
+        Fog {
+            width,
+            height,
+            visible: vec![false; len],
+            explored: vec![false; len],
+            from: None,
+        }
+    }
+    #[inline]
+    fn idx(&self, x: u16, y: u16) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(x as usize + y as usize * self.width as usize)
+        } else {
+            None
+        }
+    }
+    #[inline]
+    pub fn is_visible(&self, x: u16, y: u16) -> bool {
+        self.idx(x, y).is_some_and(|i| self.visible[i])
+    }
+    #[inline]
+    pub fn is_explored(&self, x: u16, y: u16) -> bool {
+        self.idx(x, y).is_some_and(|i| self.explored[i])
+    }
+    /// Whether the point is on a tile that can be seen
+    #[inline]
+    pub fn can_see(&self, p: Point2) -> bool {
+        let (x, y) = Grid::snap(p);
+        self.is_visible(x, y)
+    }
+    /// Whether the point is on a tile that has been seen
+    #[inline]
+    pub fn has_seen(&self, p: Point2) -> bool {
+        let (x, y) = Grid::snap(p);
+        self.is_explored(x, y)
+    }
+    /// Works out what can be seen from the point, returning whether any tiles were seen for the first time
+    ///
+    /// Open tiles are seen if a ray reaches their middle, walls if they are next to an open tile that's seen.
+    pub fn update(&mut self, pal: &Palette, grid: &Grid, from: Point2) -> bool {
+        let tile = Grid::snap(from);
+        if self.from == Some(tile) {
+            return false
+        }
+        self.from = Some(tile);
+        for v in &mut self.visible {
+            *v = false;
+        }
+
+        let (px, py) = tile;
+        let x0 = px.saturating_sub(SIGHT_RANGE);
+        let y0 = py.saturating_sub(SIGHT_RANGE);
+        let x1 = px.saturating_add(SIGHT_RANGE + 1).min(self.width);
+        let y1 = py.saturating_add(SIGHT_RANGE + 1).min(self.height);
+        let range = f32::from(SIGHT_RANGE) * 32.;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if grid.is_solid(pal, x, y) {
+                    continue
+                }
+                let dist = Point2::new(f32::from(x) * 32. + 16., f32::from(y) * 32. + 16.) - from;
+                let len = dist.norm();
+                if len > range {
+                    continue
+                }
+                if len < f32::EPSILON || grid.ray_cast(pal, from, dist, true).full() {
+                    let i = self.idx(x, y).unwrap();
+                    self.visible[i] = true;
+                }
+            }
+        }
+        let mut walls = Vec::new();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if !grid.is_solid(pal, x, y) {
+                    continue
+                }
+                let neighbours = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                if neighbours.iter().any(|&(nx, ny)| !grid.is_solid(pal, nx, ny) && self.is_visible(nx, ny)) {
+                    walls.push(self.idx(x, y).unwrap());
+                }
+            }
+        }
+        for i in walls {
+            self.visible[i] = true;
+        }
+
+        let mut new = false;
+        for (explored, &visible) in self.explored.iter_mut().zip(&self.visible) {
+            if visible && !*explored {
+                *explored = true;
+                new = true;
+            }
+        }
+        new
+    }
+    /// Darkens the tiles in view that can't be seen
+    pub fn draw(&self, ctx: &mut Context, view: Rect) -> GameResult<()> {
+        let (x0, y0) = Grid::snap(Point2::new(view.x.max(0.), view.y.max(0.)));
+        let x1 = ((view.right() / 32.).ceil().max(0.) as u16).min(self.width);
+        let y1 = ((view.bottom() / 32.).ceil().max(0.) as u16).min(self.height);
+
+        let mut mb = MeshBuilder::new();
+        let mut empty = true;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if self.is_visible(x, y) {
+                    continue
+                }
+                let colour = if self.is_explored(x, y) { REMEMBERED } else { UNSEEN };
+                let corner = Point2::new(f32::from(x), f32::from(y)) * 32.;
+                mb.rectangle(DrawMode::fill(), Rect::new(corner.x, corner.y, 32., 32.), colour);
+                empty = false;
+            }
+        }
+        if empty {
+            return Ok(())
+        }
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::default())
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    io::{res, error::ContentError},
+    util::{Point2, Vector2, angle_to_vec},
+    obj::decal::Decal,
+};
+use ggez::{
+    Context, GameResult,
+    graphics::{self, Canvas, Color, BlendMode, Drawable, DrawParam, Mesh, Vertex},
+};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::RwLock;
+
+use super::{Grid, Palette};
+
+/// Rays cast around a light to find its shadows
+const LIGHT_RAYS: u16 = 96;
+
+/// How a decal gives off light, read from its `.light` file
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct LightProperties {
+    /// How far the light reaches in pixels
+    pub radius: f32,
+    /// Red, green and blue from 0 to 1
+    #[serde(default = "white")]
+    pub colour: [f32; 3],
+    /// How bright it is right under the light
+    #[serde(default = "one")]
+    pub intensity: f32,
+}
+
+#[inline]
+fn white() -> [f32; 3] {
+    [1., 1., 1.]
+}
+#[inline]
+fn one() -> f32 {
+    1.
+}
+
+lazy_static! {
+    static ref LIGHTS: RwLock<HashMap<String, Option<LightProperties>>> = {
+        RwLock::new(HashMap::with_capacity(10))
+    };
+}
+
+/// Reads the `.light` file of a decal sprite, which most decals don't have
+fn read_light(spr: &str) -> Result<Option<LightProperties>, ContentError> {
+    let file = format!("{}.light", spr);
+    if !res::exists(&file) {
+        return Ok(None)
+    }
+    let s = res::read_to_string(&file).map_err(|e| ContentError::io(&*file, e))?;
+
+    toml::from_str(&s).map(Some).map_err(|e| ContentError::toml(file, &e))
+}
+
+/// The light a decal gives off, if any
+pub fn decal_light(spr: &str) -> Option<LightProperties> {
+    if let Some(&props) = LIGHTS.read().unwrap().get(spr) {
+        return props
+    }
+    let props = read_light(spr).unwrap_or_else(|e| {
+        warn!("{}", e);
+        None
+    });
+    LIGHTS.write().unwrap().insert(spr.to_owned(), props);
+    props
+}
+
+/// Forgets the cached light of a decal so its `.light` file is read again next time
+pub fn forget_light(spr: &str) {
+    LIGHTS.write().unwrap().remove(spr);
+}
+
+/// Checks that a decal's `.light` file, if it has one, can be read
+pub fn check_light(spr: &str) -> Result<(), ContentError> {
+    read_light(spr).map(|_| ())
+}
+
+/// Where light comes from for a light at `pos`
+///
+/// Lights inside a wall, like wall lights, shine from the side of it that's open.
+fn light_origin(pos: Point2, pal: &Palette, grid: &Grid) -> Point2 {
+    let (x, y) = Grid::snap(pos);
+    if !grid.is_solid(pal, x, y) {
+        return pos
+    }
+    let (left, top) = (f32::from(x) * 32., f32::from(y) * 32.);
+    let sides = [
+        (x.wrapping_sub(1), y, Point2::new(left - 1., pos.y)),
+        (x + 1, y, Point2::new(left + 33., pos.y)),
+        (x, y.wrapping_sub(1), Point2::new(pos.x, top - 1.)),
+        (x, y + 1, Point2::new(pos.x, top + 33.)),
+    ];
+    sides.iter()
+        .filter(|&&(sx, sy, _)| !grid.is_solid(pal, sx, sy))
+        .map(|&(_, _, p)| p)
+        .min_by(|a, b| (a - pos).norm().partial_cmp(&(b - pos).norm()).unwrap())
+        .unwrap_or(pos)
+}
+
+/// A light in the world that can be switched on and off
+#[derive(Debug)]
+pub struct Light {
+    pub pos: Point2,
+    origin: Point2,
+    pub props: LightProperties,
+    pub on: bool,
+    /// How far the light reaches in each direction around it before being blocked
+    reach: Vec<Vector2>,
+    mesh: Option<Mesh>,
+}
+
+impl Light {
+    pub fn new(pos: Point2, props: LightProperties, pal: &Palette, grid: &Grid) -> Self {
+        let origin = light_origin(pos, pal, grid);
+        let reach = (0..LIGHT_RAYS).map(|i| {
+            let dist = props.radius * angle_to_vec(2. * PI * f32::from(i) / f32::from(LIGHT_RAYS));
+            let cast = grid.ray_cast(pal, origin, dist, true);
+            if cast.full() {
+                dist
+            } else {
+                cast.into_point() - origin
+            }
+        }).collect();
+
+        Light {
+            pos,
+            origin,
+            props,
+            on: true,
+            reach,
+            mesh: None,
+        }
+    }
+    /// How much the light lights up the point, nothing if it's in a shadow
+    pub fn brightness_at(&self, p: Point2, pal: &Palette, grid: &Grid) -> f32 {
+        let dist = p - self.origin;
+        let len = dist.norm();
+        if !self.on || len >= self.props.radius {
+            return 0.
+        }
+        if len > f32::EPSILON && !grid.ray_cast(pal, self.origin, dist, true).full() {
+            return 0.
+        }
+        self.props.intensity * (1. - len / self.props.radius)
+    }
+    /// A fan of triangles getting darker away from the light
+    fn build_mesh(&self, ctx: &mut Context) -> GameResult<Mesh> {
+        let [r, g, b] = self.props.colour;
+        let vertex = |d: Vector2| {
+            let strength = self.props.intensity * (1. - d.norm() / self.props.radius);
+            Vertex {
+                pos: [self.origin.x + d.x, self.origin.y + d.y],
+                uv: [0., 0.],
+                color: [r * strength, g * strength, b * strength, 1.],
+            }
+        };
+        let mut verts = Vec::with_capacity(self.reach.len() + 1);
+        verts.push(vertex(Vector2::new(0., 0.)));
+        verts.extend(self.reach.iter().map(|&d| vertex(d)));
+
+        let n = self.reach.len() as u32;
+        let indices: Vec<u32> = (0..n).flat_map(|i| vec![0, i + 1, (i + 1) % n + 1]).collect();
+
+        let mut mesh = Mesh::from_raw(ctx, &verts, &indices, None)?;
+        mesh.set_blend_mode(Some(BlendMode::Add));
+        Ok(mesh)
+    }
+}
+
+/// The lights of a level and how dark it is everywhere else
+#[derive(Debug)]
+pub struct Lighting {
+    /// Light everywhere from 0 (pitch black) to 1 (fully lit)
+    pub ambient: f32,
+    pub lights: Vec<Light>,
+    /// The light map with the drawable size it was made for
+    canvas: Option<((f32, f32), Canvas)>,
+}
+
+impl Lighting {
+    /// Makes lights of all the decals that give off light
+    pub fn new(ambient: f32, decals: &[Decal], pal: &Palette, grid: &Grid) -> Self {
+        let lights = decals.iter()
+            .filter_map(|d| decal_light(d.spr).map(|props| Light::new(d.obj.pos, props, pal, grid)))
+            .collect();
+        Lighting {
+            ambient,
+            lights,
+            canvas: None,
+        }
+    }
+    /// Whether anything is darker than fully lit, as lights can't make it any brighter
+    #[inline]
+    pub fn is_dark(&self) -> bool {
+        self.ambient < 1.
+    }
+    /// How lit up the point is from 0 to 1
+    pub fn level_at(&self, p: Point2, pal: &Palette, grid: &Grid) -> f32 {
+        if !self.is_dark() {
+            return 1.
+        }
+        self.lights.iter()
+            .fold(self.ambient, |level, light| level + light.brightness_at(p, pal, grid))
+            .min(1.)
+    }
+    /// Switches the closest light within reach of the point on or off, returning whether there was one
+    pub fn switch_near(&mut self, p: Point2, reach: f32) -> bool {
+        let closest = self.lights.iter_mut()
+            .map(|l| ((l.pos - p).norm(), l))
+            .filter(|&(dist, _)| dist <= reach)
+            .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+        match closest {
+            Some((_, light)) => {
+                light.on = !light.on;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Draws the light map, done with the world's transformation
+    pub fn render(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if !self.is_dark() {
+            return Ok(())
+        }
+        let size = graphics::drawable_size(ctx);
+        if self.canvas.as_ref().is_none_or(|&(s, _)| s != size) {
+            let mut canvas = Canvas::with_window_size(ctx)?;
+            canvas.set_blend_mode(Some(BlendMode::Multiply));
+            self.canvas = Some((size, canvas));
+        }
+        let canvas = &self.canvas.as_ref().unwrap().1;
+
+        graphics::set_canvas(ctx, Some(canvas));
+        let a = self.ambient.max(0.);
+        graphics::clear(ctx, Color::new(a, a, a, 1.));
+        for light in self.lights.iter_mut().filter(|l| l.on) {
+            if light.mesh.is_none() {
+                light.mesh = Some(light.build_mesh(ctx)?);
+            }
+            graphics::draw(ctx, light.mesh.as_ref().unwrap(), DrawParam::default())?;
+        }
+        graphics::set_canvas(ctx, None);
+        Ok(())
+    }
+    /// Darkens the screen by the light map, done over the world before the HUD
+    pub fn draw(&self, ctx: &mut Context, width: f32, height: f32) -> GameResult<()> {
+        if let (true, Some((_, canvas))) = (self.is_dark(), &self.canvas) {
+            let img = canvas.image();
+            let param = DrawParam {
+                scale: Vector2::new(width / f32::from(img.width()), height / f32::from(img.height())).into(),
+                .. Default::default()
+            };
+            canvas.draw(ctx, param)?;
+        }
+        Ok(())
+    }
+}
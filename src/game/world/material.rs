@@ -1,15 +1,15 @@
 use crate::{
-    io::tex::Assets,
+    io::{tex::Assets, res, error::ContentError},
     util::{Point2, Vector2, sstr, Sstr},
 };
 use ggez::{graphics::{self, Image}, Context, GameResult};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::sync::RwLock;
-use std::fs::File;
-use std::io::Read;
 use std::cell::Ref;
 
+use super::Autotile;
+
 #[derive(Debug)]
 pub struct Mat {
     spr: Sstr,
@@ -24,23 +24,45 @@ lazy_static! {
 
 fn ensure(mat: &str) {
     if !MATS.read().unwrap().contains_key(mat) {
-        let props = if let Ok(mut f) = File::open(format!("resources/materials/{}.mat", mat)) {
-            let mut s = String::new();
-            f.read_to_string(&mut s).unwrap();
-
-            toml::from_str(&s).unwrap()
-        } else {
+        let props = read_properties(mat).unwrap_or_else(|e| {
+            warn!("{}", e);
             MaterialProperties::default()
-        };
+        });
         let mat_data = Mat { spr: sstr(format!("materials/{}", mat)), props};
 
         MATS.write().unwrap().insert(mat.to_owned(), mat_data);
     }
 }
 
+/// Forgets the cached properties of a material so they are read again next time they're used
+pub fn forget_material(mat: &str) {
+    MATS.write().unwrap().remove(mat);
+}
+
+/// Reads the `.mat` file of a material, a missing one giving the default properties
+fn read_properties(mat: &str) -> Result<MaterialProperties, ContentError> {
+    let file = format!("materials/{}.mat", mat);
+    if !res::exists(&file) {
+        return Ok(MaterialProperties::default())
+    }
+    let s = res::read_to_string(&file).map_err(|e| ContentError::io(&*file, e))?;
+
+    toml::from_str(&s).map_err(|e| ContentError::toml(file, &e))
+}
+
+/// Checks that a material's `.mat` file, if it has one, can be read
+pub fn check_material(mat: &str) -> Result<(), ContentError> {
+    read_properties(mat).map(|_| ())
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 struct MaterialProperties {
+    #[serde(default)]
     solid: bool,
+    /// What the material is listed under in the editor
+    #[serde(default)]
+    category: Option<String>,
+    autotile: Option<Autotile>,
 }
 
 #[inline]
@@ -50,6 +72,37 @@ pub fn is_solid(mat: &str) -> bool {
     MATS.read().unwrap()[mat].props.solid
 }
 
+/// The autotiling rules of a material if it has any
+pub fn material_autotile(mat: &str) -> Option<Autotile> {
+    ensure(mat);
+
+    MATS.read().unwrap()[mat].props.autotile.clone()
+}
+
+/// The plain sprite of a material
+pub fn material_sprite(mat: &str) -> Sstr {
+    ensure(mat);
+
+    MATS.read().unwrap()[mat].spr
+}
+
+/// The category of a material, by default walls or floors depending on whether it's solid
+pub fn material_category(mat: &str) -> String {
+    ensure(mat);
+
+    let mats = MATS.read().unwrap();
+    let props = &mats[mat].props;
+    props.category.clone().unwrap_or_else(|| if props.solid { "Walls" } else { "Floors" }.to_owned())
+}
+
+/// The names of all materials in `materials/` of the resource packs sorted alphabetically
+pub fn available_materials() -> Vec<String> {
+    res::list("materials").into_iter()
+        .filter_map(|file| file.strip_suffix(".png").map(str::to_owned))
+        .filter(|mat| mat != "missing")
+        .collect()
+}
+
 #[inline]
 pub fn get_img<'a>(ctx: &mut Context, assets: &'a Assets, mat: &str) -> Ref<'a, Image> {
     ensure(mat);
@@ -108,22 +161,35 @@ impl Palette {
             materials: mats.into_boxed_slice(),
         }
     }
-    pub fn draw_mat(&self, i: u8, ctx: &mut Context, assets: &Assets, x: f32, y: f32, dp: graphics::DrawParam) -> GameResult<()> {
-        let mat = self.materials[i as usize];
+    pub fn draw_mat(&self, i: u16, ctx: &mut Context, assets: &Assets, x: f32, y: f32, dp: graphics::DrawParam) -> GameResult<()> {
+        let mat = match self.get(i) {
+            Some(mat) => mat,
+            None => return Ok(()),
+        };
 
         let img = get_img(ctx, assets, mat);
         graphics::draw(ctx, &*img, (Point2::from(dp.dest) + Vector2::new(x, y),))
     }
-    pub fn is_solid(&self, i: u8) -> bool {
-        is_solid(self.materials[i as usize])
+    /// Whether the material is solid, materials not in the palette like empty tiles aren't
+    pub fn is_solid(&self, i: u16) -> bool {
+        self.get(i).is_some_and(is_solid)
     }
     #[inline]
-    pub fn get(&self, i: u8) -> Option<&str> {
+    pub fn get(&self, i: u16) -> Option<&str> {
         self.materials.get(i as usize).copied()
     }
     #[inline]
-    pub fn find(&self, mat: &str) -> Option<u8> {
-        self.materials.iter().position(|s| &mat == s).map(|i| i as u8)
+    pub fn find(&self, mat: &str) -> Option<u16> {
+        self.materials.iter().position(|s| &mat == s).map(|i| i as u16)
+    }
+    /// The index of the material, adding it to the end of the palette if it isn't in it
+    pub fn add(&mut self, mat: &'static str) -> u16 {
+        self.find(mat).unwrap_or_else(|| {
+            let mut mats = self.materials.to_vec();
+            mats.push(mat);
+            self.materials = mats.into_boxed_slice();
+            self.materials.len() as u16 - 1
+        })
     }
     #[inline]
     pub fn len(&self) -> usize {
@@ -0,0 +1,154 @@
+use crate::game::DELTA;
+use super::World;
+
+use std::fmt::{self, Display};
+
+/// Something the player has to do (or avoid) to complete a level
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Objective {
+    /// Kill every enemy in the level
+    KillAll,
+    /// Kill the enemy with the given index in the level's enemy list
+    Kill(usize),
+    /// Pick up the given amount of intels
+    CollectIntels(usize),
+    /// Get to the exit
+    ReachExit,
+    /// Stay alive for the given amount of seconds
+    Survive(f32),
+    /// Never get seen by an enemy
+    Undetected,
+    /// Finish the level before the given amount of seconds has passed
+    TimeLimit(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Incomplete,
+    Complete,
+    Failed,
+}
+
+/// What has happened so far in a level, used to evaluate the objectives and the score
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Progress {
+    /// Amount of ticks played
+    pub time: usize,
+    pub intels_collected: usize,
+    /// Amount of times the player went from unseen to seen
+    pub times_spotted: usize,
+    /// Amount of times an enemy started chasing the player
+    pub alerts: usize,
+    pub backstabs: usize,
+    pub pickups_used: usize,
+    pub damage_taken: f32,
+}
+
+impl Objective {
+    /// The objectives used by levels that don't specify any
+    ///
+    /// Levels with an exit require all intels to be picked up before leaving through the exit,
+    /// otherwise all enemies have to be killed.
+    pub fn implicit(exit: bool, intels: usize) -> Vec<Self> {
+        if exit {
+            let mut objs = Vec::with_capacity(2);
+            if intels > 0 {
+                objs.push(Objective::CollectIntels(intels));
+            }
+            objs.push(Objective::ReachExit);
+            objs
+        } else {
+            vec![Objective::KillAll]
+        }
+    }
+    /// Parses an objective from console arguments, e.g. `kill 2` or `survive 30`
+    pub fn parse(args: &[&str]) -> Option<Self> {
+        use self::Objective::*;
+        let arg = args.get(1);
+
+        Some(match *args.first()? {
+            "killall" => KillAll,
+            "kill" => Kill(arg?.parse().ok()?),
+            "intels" => CollectIntels(arg?.parse().ok()?),
+            "exit" => ReachExit,
+            "survive" => Survive(arg?.parse().ok()?),
+            "ghost" | "undetected" => Undetected,
+            "timelimit" => TimeLimit(arg?.parse().ok()?),
+            _ => return None,
+        })
+    }
+    /// Constraints are objectives that are fulfilled unless they are failed
+    pub fn is_constraint(self) -> bool {
+        matches!(self, Objective::Undetected | Objective::TimeLimit(_))
+    }
+    pub fn status(self, world: &World, progress: &Progress) -> Status {
+        use self::Objective::*;
+        let secs = progress.time as f32 * DELTA;
+
+        let done = match self {
+            KillAll => world.enemies.is_empty(),
+            Kill(id) => world.enemies.iter().all(|e| e.id != id),
+            CollectIntels(n) => progress.intels_collected >= n,
+            ReachExit => match world.exit {
+                Some(p) => (p - world.player.obj.pos).norm() < 32.,
+                None => false,
+            },
+            Survive(t) => secs >= t,
+            Undetected => if progress.times_spotted > 0 {
+                return Status::Failed
+            } else {
+                true
+            },
+            TimeLimit(t) => if secs > t {
+                return Status::Failed
+            } else {
+                true
+            },
+        };
+
+        if done {
+            Status::Complete
+        } else {
+            Status::Incomplete
+        }
+    }
+    /// Text describing how far the player is with this objective
+    pub fn progress_text(self, world: &World, progress: &Progress) -> String {
+        use self::Objective::*;
+        let secs = progress.time as f32 * DELTA;
+
+        match self {
+            KillAll => format!("Kill everyone ({} left)", world.enemies.len()),
+            CollectIntels(n) => format!("Collect intel ({} / {})", progress.intels_collected.min(n), n),
+            Survive(t) => format!("Survive ({:.0} / {:.0}s)", secs.min(t), t),
+            TimeLimit(t) => format!("Time limit ({:.0}s left)", (t - secs).max(0.)),
+            obj => obj.to_string(),
+        }
+    }
+}
+
+/// Checks whether all objectives are complete
+///
+/// A list of only constraints can never be won.
+pub fn objectives_won(statuses: &[(Objective, Status)]) -> bool {
+    statuses.iter().all(|&(_, s)| s == Status::Complete) && statuses.iter().any(|&(o, _)| !o.is_constraint())
+}
+/// Checks whether any objective has been failed
+pub fn objectives_failed(statuses: &[(Objective, Status)]) -> bool {
+    statuses.iter().any(|&(_, s)| s == Status::Failed)
+}
+
+impl Display for Objective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Objective::*;
+        match *self {
+            KillAll => "Kill everyone".fmt(f),
+            Kill(i) => write!(f, "Assassinate target #{}", i),
+            CollectIntels(n) => write!(f, "Collect {} intel", n),
+            ReachExit => "Reach the exit".fmt(f),
+            Survive(t) => write!(f, "Survive for {:.0}s", t),
+            Undetected => "Stay undetected".fmt(f),
+            TimeLimit(t) => write!(f, "Finish within {:.0}s", t),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{
+    util::{Point2, Vector2, sstr},
+    io::{res, error::ContentError},
+    obj::{Object, enemy::Enemy, decal::Decal},
+};
+use ggez::{GameResult, GameError};
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::Write;
+
+use super::{Grid, Layer, Level, Palette};
+
+/// A piece of a level with tiles and the entities on them
+///
+/// Prefabs are stored as text so they can be put on the clipboard
+/// and saved in `prefabs/` of the game's own resources, or come from mods, to be stamped into other levels.
+/// Positions are relative to the top left corner of the tiles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prefab {
+    width: u16,
+    materials: Vec<String>,
+    /// Indices into `materials` of the wall layer
+    tiles: Vec<u16>,
+    /// Left out if the prefab has nothing on the layer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    floor: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    overlay: Vec<u16>,
+    #[serde(default)]
+    intels: Vec<(f32, f32)>,
+    #[serde(default)]
+    pickups: Vec<(f32, f32, u8)>,
+    #[serde(default)]
+    enemies: Vec<PrefabObject>,
+    #[serde(default)]
+    decals: Vec<PrefabDecal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PrefabObject {
+    x: f32,
+    y: f32,
+    rot: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PrefabDecal {
+    x: f32,
+    y: f32,
+    rot: f32,
+    spr: String,
+}
+
+/// The entities of a prefab placed in a level
+#[derive(Debug, Default)]
+pub struct PrefabEntities {
+    pub enemies: Vec<Enemy>,
+    pub intels: Vec<Point2>,
+    pub pickups: Vec<(Point2, u8)>,
+    pub decals: Vec<Decal>,
+}
+
+impl Prefab {
+    /// Copies the given entities of the level and the tiles under them
+    ///
+    /// Returns `None` if nothing is given.
+    pub fn copy(level: &Level, enemies: &[usize], intels: &[usize], pickups: &[usize], decals: &[usize]) -> Option<Self> {
+        let points: Vec<Point2> = enemies.iter().map(|&i| level.enemies[i].pl.obj.pos)
+            .chain(intels.iter().map(|&i| level.intels[i]))
+            .chain(pickups.iter().map(|&i| level.pickups[i].0))
+            .chain(decals.iter().map(|&i| level.decals[i].obj.pos))
+            .collect();
+        let first = *points.first()?;
+
+        let (min, max) = points.iter().fold((first, first), |(min, max), p| {
+            (Point2::new(min.x.min(p.x), min.y.min(p.y)), Point2::new(max.x.max(p.x), max.y.max(p.y)))
+        });
+        // Entities outside the grid to the top or left get the edge tiles
+        let (x0, y0) = Grid::snap_coords(min.x.max(0.), min.y.max(0.));
+        let (x1, y1) = Grid::snap_coords(max.x.max(0.), max.y.max(0.));
+        let origin = Point2::new(f32::from(x0) * 32., f32::from(y0) * 32.);
+        let rel = |p: Point2| p - origin;
+
+        let mut materials = Vec::new();
+        let mut copy_layer = |layer: Layer| {
+            let grid = level.layer(layer);
+            let mut tiles = Vec::with_capacity((x1 - x0 + 1) as usize * (y1 - y0 + 1) as usize);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    let mat = match grid.get(x, y) {
+                        Some(Grid::EMPTY) => {
+                            tiles.push(Grid::EMPTY);
+                            continue
+                        }
+                        m => m.and_then(|m| level.palette.get(m)).unwrap_or("missing"),
+                    };
+                    let i = materials.iter().position(|m| m == mat).unwrap_or_else(|| {
+                        materials.push(mat.to_owned());
+                        materials.len() - 1
+                    });
+                    tiles.push(i as u16);
+                }
+            }
+            tiles
+        };
+        let tiles = copy_layer(Layer::Wall);
+        let floor = Some(copy_layer(Layer::Floor)).filter(|t| t.iter().any(|&m| m != Grid::EMPTY)).unwrap_or_default();
+        let overlay = Some(copy_layer(Layer::Overlay)).filter(|t| t.iter().any(|&m| m != Grid::EMPTY)).unwrap_or_default();
+
+        Some(Prefab {
+            width: x1 - x0 + 1,
+            materials,
+            tiles,
+            floor,
+            overlay,
+            intels: intels.iter().map(|&i| rel(level.intels[i])).map(|v| (v.x, v.y)).collect(),
+            pickups: pickups.iter().map(|&i| {
+                let (p, kind) = level.pickups[i];
+                let v = rel(p);
+                (v.x, v.y, kind)
+            }).collect(),
+            enemies: enemies.iter().map(|&i| {
+                let Object{pos, rot} = level.enemies[i].pl.obj;
+                let v = rel(pos);
+                PrefabObject{x: v.x, y: v.y, rot}
+            }).collect(),
+            decals: decals.iter().map(|&i| {
+                let Decal{obj: Object{pos, rot}, spr} = level.decals[i];
+                let v = rel(pos);
+                PrefabDecal{x: v.x, y: v.y, rot, spr: spr.to_owned()}
+            }).collect(),
+        })
+    }
+    #[inline]
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+    #[inline]
+    pub fn height(&self) -> u16 {
+        (self.tiles.len() / self.width as usize) as u16
+    }
+    /// The palette needed to place this prefab, the level's palette with the prefab's materials added
+    pub fn palette(&self, pal: &Palette) -> Palette {
+        pal.clone().and(&Palette::new(self.materials.iter().map(|m| sstr(&**m)).collect()))
+    }
+    /// The tiles of the prefab's layers placed with its top left corner at the given tile
+    ///
+    /// Empty tiles are left out so they don't erase what's under them.
+    /// The corner can be outside the grid, tiles that would be at negative coordinates are left out.
+    /// The materials are indices into the palette given by `Prefab::palette`.
+    pub fn tiles(&self, pal: &Palette, x: i32, y: i32) -> Vec<(Layer, u16, u16, u16)> {
+        let mats: Vec<u16> = self.materials.iter().map(|m| pal.find(m).unwrap_or(0)).collect();
+        let layers = [(Layer::Floor, &self.floor), (Layer::Wall, &self.tiles), (Layer::Overlay, &self.overlay)];
+        let width = self.width as usize;
+
+        layers.iter().flat_map(|&(layer, tiles)| tiles.iter().enumerate().map(move |(i, &m)| (layer, i, m)))
+            .filter(|&(_, _, m)| m != Grid::EMPTY)
+            .filter_map(|(layer, i, m)| {
+                let tx = u16::try_from(i64::from(x) + (i % width) as i64).ok()?;
+                let ty = u16::try_from(i64::from(y) + (i / width) as i64).ok()?;
+                Some((layer, tx, ty, mats[m as usize]))
+            })
+            .collect()
+    }
+    /// The entities of the prefab placed with its top left corner at the given tile
+    pub fn entities(&self, x: i32, y: i32) -> PrefabEntities {
+        let origin = Point2::new(x as f32 * 32., y as f32 * 32.);
+        let at = |x: f32, y: f32| origin + Vector2::new(x, y);
+
+        PrefabEntities {
+            enemies: self.enemies.iter().map(|e| Enemy::new(Object::with_rot(at(e.x, e.y), e.rot))).collect(),
+            intels: self.intels.iter().map(|&(x, y)| at(x, y)).collect(),
+            pickups: self.pickups.iter().map(|&(x, y, kind)| (at(x, y), kind)).collect(),
+            decals: self.decals.iter().map(|d| Decal::new(Object::with_rot(at(d.x, d.y), d.rot), sstr(&*d.spr))).collect(),
+        }
+    }
+    /// Reads a prefab from the clipboard's text
+    #[inline]
+    pub fn from_text(s: &str) -> Result<Self, ContentError> {
+        Self::parse(s, "clipboard")
+    }
+    fn parse(s: &str, file: &str) -> Result<Self, ContentError> {
+        let prefab: Self = toml::from_str(s).map_err(|e| ContentError::toml(file, &e))?;
+        if prefab.width == 0 || !prefab.tiles.len().is_multiple_of(prefab.width as usize) {
+            return Err(ContentError::invalid(file, "Prefab tiles don't fit its width"));
+        }
+        if [&prefab.floor, &prefab.overlay].iter().any(|l| !l.is_empty() && l.len() != prefab.tiles.len()) {
+            return Err(ContentError::invalid(file, "Prefab layers have different sizes"));
+        }
+        let all_tiles = prefab.tiles.iter().chain(&prefab.floor).chain(&prefab.overlay);
+        if all_tiles.copied().any(|m| m != Grid::EMPTY && m as usize >= prefab.materials.len()) {
+            return Err(ContentError::invalid(file, "Prefab tile has no material"));
+        }
+        Ok(prefab)
+    }
+    pub fn to_text(&self) -> String {
+        toml::to_string(self).expect("prefabs are always valid toml")
+    }
+    /// Whether the name can be used for a prefab file, which has to be right in the prefab directory
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty() && !name.contains(['/', '\\']) && !name.contains("..")
+    }
+    pub fn load(name: &str) -> Result<Self, ContentError> {
+        let file = format!("prefabs/{}.toml", name);
+        if !Self::is_valid_name(name) {
+            return Err(ContentError::invalid(file, "Invalid prefab name"));
+        }
+        let s = res::read_to_string(&file).map_err(|e| ContentError::io(&*file, e))?;
+        Self::parse(&s, &file)
+    }
+    pub fn save(&self, name: &str) -> GameResult<()> {
+        if !Self::is_valid_name(name) {
+            return Err(GameError::ResourceLoadError(format!("Invalid prefab name {}", name)));
+        }
+        let path = res::base_path(&format!("prefabs/{}.toml", name));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        File::create(path)?.write_all(self.to_text().as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level_with_intel() -> Level {
+        let mut level = Level::new(Palette::default(), 4, 4);
+        level.grid.insert(1, 1, 1);
+        level.intels.push(Point2::new(48., 48.));
+        level.intels.push(Point2::new(80., 80.));
+        level
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let level = level_with_intel();
+        let prefab = Prefab::copy(&level, &[], &[0, 1], &[], &[]).unwrap();
+        let parsed = Prefab::from_text(&prefab.to_text()).unwrap();
+
+        assert_eq!((parsed.width(), parsed.height()), (2, 2));
+        assert_eq!(parsed.to_text(), prefab.to_text());
+        assert_eq!(parsed.entities(1, 1).intels, level.intels);
+    }
+
+    #[test]
+    fn paste_off_the_top_left() {
+        let level = level_with_intel();
+        let prefab = Prefab::copy(&level, &[], &[0, 1], &[], &[]).unwrap();
+        let pal = prefab.palette(&level.palette);
+
+        let tiles = prefab.tiles(&pal, -1, -1);
+        assert!(tiles.iter().all(|&(_, x, y, _)| (x, y) == (0, 0)));
+        assert_eq!(tiles.iter().find(|&&(layer, ..)| layer == Layer::Wall).map(|t| t.3), level.grid.get(2, 2));
+    }
+
+    #[test]
+    fn empty_tiles_are_transparent() {
+        let mut level = level_with_intel();
+        level.overlay.insert(1, 1, 2);
+        let prefab = Prefab::copy(&level, &[], &[0, 1], &[], &[]).unwrap();
+
+        let overlay: Vec<_> = prefab.tiles(&prefab.palette(&level.palette), 0, 0).into_iter()
+            .filter(|&(layer, ..)| layer == Layer::Overlay)
+            .collect();
+        assert_eq!(overlay, vec![(Layer::Overlay, 0, 0, 2)]);
+    }
+
+    #[test]
+    fn rejects_bad_text() {
+        assert!(Prefab::from_text("width = 2\nmaterials = []\ntiles = [0]").is_err());
+        assert!(Prefab::from_text("width = 1\nmaterials = []\ntiles = [0]").is_err());
+        assert!(Prefab::from_text("width = 1\nmaterials = [\"wall\"]\ntiles = [0]").is_ok());
+    }
+
+    #[test]
+    fn names_stay_in_the_prefab_directory() {
+        assert!(Prefab::is_valid_name("office"));
+        assert!(!Prefab::is_valid_name(""));
+        assert!(!Prefab::is_valid_name("../../foo"));
+        assert!(!Prefab::is_valid_name("a/b"));
+        assert!(!Prefab::is_valid_name("a\\b"));
+    }
+}
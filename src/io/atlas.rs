@@ -0,0 +1,130 @@
+use ggez::{Context, GameResult, graphics::{Image, Rect}};
+use image::RgbaImage;
+
+use super::res;
+
+/// Width and height of an atlas page in pixels
+const PAGE_SIZE: u32 = 1024;
+/// Space around every sprite filled with its edge pixels so neighbours don't bleed in
+const PADDING: u32 = 1;
+
+/// A texture holding many sprites so they can be drawn in one batch
+pub struct Page {
+    pub image: Image,
+    /// The sprites on the page with their parts of it in normalised coordinates
+    pub regions: Vec<(String, Rect)>,
+}
+
+/// Loads every PNG in the directories of the resource packs and packs them onto as few pages as possible
+///
+/// Sprites are named like they are for `Assets::get_img`. Those that can't be read or
+/// are too big for a page are left out.
+pub fn build(ctx: &mut Context, dirs: &[&str]) -> GameResult<Vec<Page>> {
+    let mut sprites = Vec::new();
+    for dir in dirs {
+        for file in res::list(dir) {
+            let name = match file.strip_suffix(".png") {
+                Some(stem) => format!("{}/{}", dir, stem),
+                None => continue,
+            };
+            match load(&name) {
+                Ok(ref img) if img.width() == 0 || img.height() == 0 => warn!("{} is empty", name),
+                Ok(img) if img.width() + 2 * PADDING <= PAGE_SIZE && img.height() + 2 * PADDING <= PAGE_SIZE => sprites.push((name, img)),
+                Ok(_) => warn!("{} is too big for the texture atlas", name),
+                Err(e) => warn!("Couldn't load {} into the texture atlas: {}", name, e),
+            }
+        }
+    }
+    // Tallest first so the shelves waste less space
+    sprites.sort_by_key(|(_, img)| std::cmp::Reverse(img.height()));
+
+    let mut pages = Vec::new();
+    let mut packer = Packer::default();
+    for (name, img) in sprites {
+        let (w, h) = (img.width() + 2 * PADDING, img.height() + 2 * PADDING);
+        let (x, y) = match packer.place(w, h) {
+            Some(pos) => pos,
+            None => {
+                pages.push(packer.finish(ctx)?);
+                packer = Packer::default();
+                packer.place(w, h).expect("sprite fits on an empty page")
+            }
+        };
+        packer.blit(&img, x + PADDING, y + PADDING);
+
+        let size = PAGE_SIZE as f32;
+        let src = Rect::new((x + PADDING) as f32 / size, (y + PADDING) as f32 / size, img.width() as f32 / size, img.height() as f32 / size);
+        packer.regions.push((name, src));
+    }
+    if !packer.regions.is_empty() {
+        pages.push(packer.finish(ctx)?);
+    }
+
+    Ok(pages)
+}
+
+/// Decodes a sprite's PNG from the resource packs
+pub fn load(name: &str) -> GameResult<RgbaImage> {
+    let buf = res::read(&format!("{}.png", name))?;
+
+    image::load_from_memory(&buf)
+        .map(|img| img.to_rgba())
+        .map_err(|e| ggez::GameError::ResourceLoadError(format!("{}", e)))
+}
+
+/// Puts sprites in rows from the top of a page
+struct Packer {
+    pixels: RgbaImage,
+    regions: Vec<(String, Rect)>,
+    x: u32,
+    y: u32,
+    shelf_height: u32,
+}
+
+impl Default for Packer {
+    fn default() -> Self {
+        Packer {
+            pixels: RgbaImage::new(PAGE_SIZE, PAGE_SIZE),
+            regions: Vec::new(),
+            x: 0,
+            y: 0,
+            shelf_height: 0,
+        }
+    }
+}
+
+impl Packer {
+    /// Finds space for a sprite, returning `None` if the page is full
+    fn place(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        if self.x + w > PAGE_SIZE {
+            self.x = 0;
+            self.y += self.shelf_height;
+            self.shelf_height = 0;
+        }
+        if self.y + h > PAGE_SIZE {
+            return None
+        }
+        let pos = (self.x, self.y);
+        self.x += w;
+        self.shelf_height = self.shelf_height.max(h);
+        Some(pos)
+    }
+    /// Copies the sprite onto the page with its edges stretched out into the padding
+    fn blit(&mut self, img: &RgbaImage, x: u32, y: u32) {
+        let (w, h) = (img.width() as i64, img.height() as i64);
+        let pad = i64::from(PADDING);
+        for dy in -pad..h + pad {
+            for dx in -pad..w + pad {
+                let px = *img.get_pixel(dx.max(0).min(w - 1) as u32, dy.max(0).min(h - 1) as u32);
+                self.pixels.put_pixel((i64::from(x) + dx) as u32, (i64::from(y) + dy) as u32, px);
+            }
+        }
+    }
+    fn finish(self, ctx: &mut Context) -> GameResult<Page> {
+        let image = Image::from_rgba8(ctx, PAGE_SIZE as u16, PAGE_SIZE as u16, &self.pixels.into_raw())?;
+        Ok(Page {
+            image,
+            regions: self.regions,
+        })
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt::{self, Display};
+use std::io;
+
+use ggez::GameError;
+
+/// An error in the game's content, saying which file it's in and where
+#[derive(Debug)]
+pub enum ContentError {
+    /// The file couldn't be read
+    Io {
+        file: String,
+        error: io::Error,
+    },
+    /// The file is invalid, with the line the problem is on if it's known
+    Invalid {
+        file: String,
+        line: Option<usize>,
+        message: String,
+    },
+    /// A section of a level file couldn't be read
+    Section {
+        file: String,
+        section: String,
+        message: String,
+    },
+    /// A level in a campaign couldn't be loaded
+    CampaignLevel {
+        file: String,
+        line: usize,
+        error: Box<ContentError>,
+    },
+    /// A campaign that has no levels
+    EmptyCampaign {
+        file: String,
+    },
+}
+
+impl ContentError {
+    #[inline]
+    pub fn io<S: Into<String>>(file: S, error: io::Error) -> Self {
+        ContentError::Io{file: file.into(), error}
+    }
+    #[inline]
+    pub fn invalid<S: Into<String>, M: Display>(file: S, message: M) -> Self {
+        ContentError::Invalid{file: file.into(), line: None, message: message.to_string()}
+    }
+    /// An invalid TOML file, with the line from the error
+    pub fn toml<S: Into<String>>(file: S, error: &toml::de::Error) -> Self {
+        ContentError::Invalid {
+            file: file.into(),
+            line: error.line_col().map(|(line, _)| line + 1),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl Display for ContentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ContentError::*;
+        match self {
+            Io{file, error} => write!(f, "{}: {}", file, error),
+            Invalid{file, line: Some(line), message} => write!(f, "{}:{}: {}", file, line, message),
+            Invalid{file, line: None, message} => write!(f, "{}: {}", file, message),
+            Section{file, section, message} => write!(f, "{}, section {}: {}", file, section, message),
+            CampaignLevel{file, line, error} => write!(f, "{}:{}: {}", file, line, error),
+            EmptyCampaign{file} => write!(f, "{}: campaign has no levels", file),
+        }
+    }
+}
+
+impl std::error::Error for ContentError {}
+
+impl From<ContentError> for GameError {
+    #[inline]
+    fn from(e: ContentError) -> Self {
+        GameError::ResourceLoadError(e.to_string())
+    }
+}
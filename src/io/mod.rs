@@ -1,4 +1,8 @@
 pub mod tex;
+pub mod atlas;
 pub mod save;
 pub mod snd;
 pub mod btn;
+pub mod watch;
+pub mod res;
+pub mod error;
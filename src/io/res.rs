@@ -0,0 +1,184 @@
+//! Finding resource files in the resource packs
+//!
+//! Files are named by their path in a pack, like `materials/wall.png`. The mods are searched first,
+//! in priority order, and then the game's own `resources/` directory. Mods can be directories or zip
+//! archives laid out like `resources/`, and can also have `campaigns/` and `levels/`.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+
+use lazy_static::lazy_static;
+use zip::ZipArchive;
+
+/// Where mods are looked for to be listed in the menu
+pub const MODS_DIR: &str = "mods";
+
+enum Pack {
+    Dir(PathBuf),
+    Zip {
+        path: PathBuf,
+        archive: Mutex<ZipArchive<File>>,
+        files: Vec<String>,
+    },
+}
+
+impl Pack {
+    fn open(path: &Path) -> io::Result<Self> {
+        if path.is_dir() {
+            return Ok(Pack::Dir(path.to_owned()))
+        }
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let files = (0..archive.len())
+            .filter_map(|i| archive.by_index(i).ok().filter(|f| f.is_file()).map(|f| f.name().to_owned()))
+            .collect();
+
+        Ok(Pack::Zip {
+            path: path.to_owned(),
+            archive: Mutex::new(archive),
+            files,
+        })
+    }
+    fn path(&self) -> &Path {
+        match self {
+            Pack::Dir(path) | Pack::Zip{path, ..} => path,
+        }
+    }
+    fn read(&self, file: &str) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match self {
+            Pack::Dir(path) => {
+                File::open(path.join(file))?.read_to_end(&mut buf)?;
+            }
+            Pack::Zip{archive, ..} => {
+                archive.lock().unwrap().by_name(file)?.read_to_end(&mut buf)?;
+            }
+        }
+        Ok(buf)
+    }
+    fn exists(&self, file: &str) -> bool {
+        match self {
+            Pack::Dir(path) => path.join(file).is_file(),
+            Pack::Zip{files, ..} => files.iter().any(|f| f == file),
+        }
+    }
+    fn list(&self, dir: &str) -> Vec<String> {
+        match self {
+            Pack::Dir(path) => std::fs::read_dir(path.join(dir))
+                .map(|entries| entries.filter_map(Result::ok)
+                    .filter(|e| e.path().is_file())
+                    .filter_map(|e| e.file_name().to_str().map(str::to_owned))
+                    .collect())
+                .unwrap_or_default(),
+            Pack::Zip{files, ..} => files.iter()
+                .filter_map(|f| f.strip_prefix(dir)?.strip_prefix('/'))
+                .filter(|name| !name.contains('/'))
+                .map(str::to_owned)
+                .collect(),
+        }
+    }
+    /// When the file was last changed, which for a zip is when the whole archive was
+    fn modified(&self, file: &str) -> Option<SystemTime> {
+        match self {
+            Pack::Dir(path) => std::fs::metadata(path.join(file)).and_then(|m| m.modified()).ok(),
+            Pack::Zip{path, ..} => std::fs::metadata(path).and_then(|m| m.modified()).ok(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref BASE: RwLock<Pack> = RwLock::new(Pack::Dir("resources".into()));
+    /// Highest priority first
+    static ref MODS: RwLock<Vec<Pack>> = RwLock::new(Vec::new());
+}
+
+/// Sets the directory of the game's own resources, which is `resources/` by default
+pub fn set_base<P: Into<PathBuf>>(path: P) {
+    *BASE.write().unwrap() = Pack::Dir(path.into());
+}
+/// Where a file is in the game's own resources, for writing to it
+pub fn base_path(file: &str) -> PathBuf {
+    BASE.read().unwrap().path().join(file)
+}
+/// Replaces the mods with the given ones, the first having the highest priority
+pub fn set_mods<P: AsRef<Path>>(paths: &[P]) -> io::Result<()> {
+    let packs = paths.iter().map(|p| Pack::open(p.as_ref())).collect::<io::Result<_>>()?;
+    *MODS.write().unwrap() = packs;
+    Ok(())
+}
+/// The paths of the mods in use, the first having the highest priority
+pub fn mods() -> Vec<PathBuf> {
+    MODS.read().unwrap().iter().map(|p| p.path().to_owned()).collect()
+}
+/// The directories and zip archives in `mods/`
+pub fn available_mods() -> Vec<PathBuf> {
+    let mut mods: Vec<PathBuf> = std::fs::read_dir(MODS_DIR)
+        .map(|entries| entries.filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.is_dir() || p.extension() == Some("zip".as_ref()))
+            .collect())
+        .unwrap_or_default();
+    mods.sort();
+    mods
+}
+
+/// Calls the function with every pack in priority order until it returns something
+fn find<T, F: FnMut(&Pack) -> Option<T>>(mut f: F) -> Option<T> {
+    MODS.read().unwrap().iter().find_map(&mut f).or_else(|| f(&BASE.read().unwrap()))
+}
+
+/// Reads a resource file from the first pack that has it
+pub fn read(file: &str) -> io::Result<Vec<u8>> {
+    find(|pack| if pack.exists(file) { Some(pack.read(file)) } else { None })
+        .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found", file))))
+}
+pub fn read_to_string(file: &str) -> io::Result<String> {
+    String::from_utf8(read(file)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+/// Whether any pack has the resource file
+pub fn exists(file: &str) -> bool {
+    find(|pack| if pack.exists(file) { Some(()) } else { None }).is_some()
+}
+/// When the resource file that would be read was last changed
+pub fn modified(file: &str) -> Option<SystemTime> {
+    find(|pack| if pack.exists(file) { pack.modified(file) } else { None })
+}
+/// The names of the files in a directory of all the packs, sorted
+pub fn list(dir: &str) -> Vec<String> {
+    let mut files: Vec<String> = MODS.read().unwrap().iter()
+        .flat_map(|pack| pack.list(dir))
+        .chain(BASE.read().unwrap().list(dir))
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Reads a campaign or level from the mods if one has it, or else from the game's directory
+pub fn read_content<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    let file = path.to_string_lossy().replace('\\', "/");
+    let modded = MODS.read().unwrap().iter()
+        .find(|pack| pack.exists(&file))
+        .map(|pack| pack.read(&file));
+
+    match modded {
+        Some(res) => res,
+        None => std::fs::read(path),
+    }
+}
+/// The campaigns of the mods and in `campaigns/` by their paths
+pub fn campaigns() -> Vec<PathBuf> {
+    let mut campaigns: Vec<PathBuf> = MODS.read().unwrap().iter()
+        .flat_map(|pack| pack.list("campaigns"))
+        .map(|name| Path::new("campaigns").join(name))
+        .chain(std::fs::read_dir("campaigns")
+            .map(|entries| entries.filter_map(Result::ok).map(|e| e.path()).collect::<Vec<_>>())
+            .unwrap_or_default())
+        .collect();
+    campaigns.sort();
+    campaigns.dedup();
+    campaigns
+}
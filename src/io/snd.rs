@@ -3,11 +3,22 @@ use std::collections::HashMap;
 use ggez::{Context, GameResult};
 use ggez::audio::{Source, SoundData, SoundSource};
 
+use super::res;
+
 const EFFECTS_LIMIT: usize = 25;
+/// Volume of effects at full volume in the settings
+const EFFECTS_VOLUME: f32 = 0.1;
+/// Volume of music at full volume in the settings
+const MUSIC_VOLUME: f32 = 0.25;
+
+/// Music the game plays, loaded from `.ogg` files
+pub const MUSIC: [&str; 2] = ["music", "victory"];
+/// Sound effects the game plays, loaded from `.wav` files
+pub const EFFECTS: [&str; 4] = ["death", "hit", "hling", "shuk"];
 
-fn new_source(ctx: &mut Context, data: &SoundData) -> GameResult<Source> {
+fn new_source(ctx: &mut Context, data: &SoundData, volume: f32) -> GameResult<Source> {
     Source::from_data(ctx, data.clone()).map(|mut src| {
-        src.set_volume(0.1);
+        src.set_volume(volume);
         src
     })
 }
@@ -17,6 +28,8 @@ pub struct MediaPlayer {
     // containers for sources
     music_sources: HashMap<String, Source>,
     effects: Vec<Source>,
+    music_volume: f32,
+    effects_volume: f32,
 }
 
 impl Default for MediaPlayer {
@@ -33,17 +46,27 @@ impl MediaPlayer {
             effects: Vec::with_capacity(10),
             music_sources: HashMap::new(),
             data: HashMap::with_capacity(24),
+            music_volume: MUSIC_VOLUME,
+            effects_volume: EFFECTS_VOLUME,
+        }
+    }
+    /// Sets the volumes from 0 to 1, the master volume scaling the others
+    pub fn set_volumes(&mut self, master: f32, music: f32, effects: f32) {
+        self.music_volume = MUSIC_VOLUME * master * music;
+        self.effects_volume = EFFECTS_VOLUME * master * effects;
+        for src in self.music_sources.values_mut() {
+            src.set_volume(self.music_volume);
         }
     }
-    pub fn add_effect(&mut self, ctx: &mut Context, s: &str) -> GameResult<&mut SoundData> {
-        let data = SoundData::new(ctx, format!("/sounds/{}.wav", s))?;
+    pub fn add_effect(&mut self, s: &str) -> GameResult<&mut SoundData> {
+        let data = SoundData::from_bytes(&res::read(&format!("sounds/{}.wav", s))?);
         self.data.insert(s.to_owned(), data);
         Ok(self.data.get_mut(s).unwrap())
     }
     pub fn register_music<S: Into<String>>(&mut self, ctx: &mut Context, s: S, repeat: bool) -> GameResult<()> {
         let s = s.into();
 
-        let data = SoundData::new(ctx, format!("/sounds/{}.ogg", s))?;
+        let data = SoundData::from_bytes(&res::read(&format!("sounds/{}.ogg", s))?);
         self.data.insert(s.clone(), data);
 
         let cache = self.new_cache(ctx, &s, repeat)?;
@@ -52,15 +75,16 @@ impl MediaPlayer {
     }
     pub fn play(&mut self, ctx: &mut Context, s: &str) -> GameResult<()> {
         let snd;
+        let volume = self.effects_volume;
 
         if let Some(music) = self.music_sources.get_mut(s) {
             return music.play();
         } else if let Some(s) = self.data.get(s) {
             snd = s;
         } else {
-            snd = self.add_effect(ctx, s)?;
+            snd = self.add_effect(s)?;
         }
-        let mut src = new_source(ctx, snd)?;
+        let mut src = new_source(ctx, snd, volume)?;
         src.play()?;
 
         self.clear_effects();
@@ -76,18 +100,34 @@ impl MediaPlayer {
     fn new_cache(&self, ctx: &mut Context, s: &str, repeat: bool) -> GameResult<Source> {
         Source::from_data(ctx, self.data[s].clone())
             .map(|mut src| {
-                src.set_volume(0.25);
+                src.set_volume(self.music_volume);
                 src.set_repeat(repeat);
                 src
             })
     }
+    /// Loads a sound again from its file
+    pub fn reload(&mut self, ctx: &mut Context, s: &str) -> GameResult<()> {
+        if let Some(mut music) = self.music_sources.remove(s) {
+            let playing = music.playing();
+            music.stop();
+            self.register_music(ctx, s, music.repeat())?;
+            if playing {
+                self.play(ctx, s)?;
+            }
+        } else {
+            // Effects are loaded when they're next played
+            self.data.remove(s);
+        }
+        Ok(())
+    }
     pub fn stop(&mut self, ctx: &mut Context, s: &str) -> GameResult<()> {
         let repeat;
         if let Some(music_source) = self.music_sources.get_mut(s) {
             repeat = music_source.repeat();
             music_source.stop();
         } else {
-            panic!("{:?} can't be stopped", s);
+            warn!("{:?} can't be stopped since it isn't music", s);
+            return Ok(());
         }
 
         if repeat {
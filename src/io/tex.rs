@@ -4,30 +4,114 @@ use std::cell::{RefCell, Ref};
 use crate::util::{Point2, Vector2};
 
 use ggez::{Context, GameResult, GameError};
-use ggez::graphics::{Image, Font, Text, TextFragment, Drawable, DrawParam, Scale};
+use ggez::graphics::{Image, Font, Text, TextFragment, Drawable, DrawParam, Scale, Rect};
+
+use super::{atlas, res};
 
 /// All the assets
 pub struct Assets {
     texes: RefCell<HashMap<String, Image>>,
+    /// Pages of the texture atlas
+    atlas: Vec<Image>,
+    /// Where sprites are in the atlas, by page and part of it
+    regions: HashMap<String, (usize, Rect)>,
     /// The font used for all the text
     pub font: Font,
 }
 
 const MISSING_TEXTURE: &str = "materials/missing";
+/// The directories whose sprites are put in the texture atlas
+const ATLAS_DIRS: [&str; 5] = ["materials", "materials/autotile", "decorations", "pickups", "common"];
+
+/// Where a sprite's image comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpriteSource {
+    /// A page of the texture atlas
+    Atlas(usize),
+    /// Its own image
+    Image(&'static str),
+}
+
+/// A sprite as a part of an image, to be drawn with `src` set to its region
+///
+/// Sprites with the same source share an image and can be drawn in the same `SpriteBatch`.
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    pub source: SpriteSource,
+    pub image: Image,
+    /// The sprite's part of the image in normalised coordinates
+    pub src: Rect,
+}
 
 impl Assets {
-    /// Initialises the assets with the context
+    /// Initialises the assets with the context and builds the texture atlas
     pub fn new(ctx: &mut Context) -> GameResult<Self> {
-        Ok(Assets {
+        let mut assets = Assets {
             texes: RefCell::new(HashMap::with_capacity(64)),
-            font: Font::new(ctx, "/common/DroidSansMono.ttf")?,
-        })
+            atlas: Vec::new(),
+            regions: HashMap::new(),
+            font: Font::new_glyph_font_bytes(ctx, &res::read("common/DroidSansMono.ttf")?)?,
+        };
+        assets.build_atlas(ctx)?;
+
+        Ok(assets)
+    }
+    fn build_atlas(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.atlas.clear();
+        self.regions.clear();
+        for (i, page) in atlas::build(ctx, &ATLAS_DIRS)?.into_iter().enumerate() {
+            self.regions.extend(page.regions.into_iter().map(|(name, src)| (name, (i, src))));
+            self.atlas.push(page.image);
+        }
+        info!("Packed {} sprites into {} atlas pages", self.regions.len(), self.atlas.len());
+        Ok(())
+    }
+    /// Loads the sprites again from their files, rebuilding the texture atlas if any of them are in it
+    ///
+    /// Sprites gotten from before should not be drawn afterwards.
+    pub fn reload(&mut self, ctx: &mut Context, sprites: &[&str]) -> GameResult<()> {
+        {
+            let mut texes = self.texes.borrow_mut();
+            for s in sprites {
+                texes.remove(*s);
+            }
+        }
+        if sprites.iter().any(|s| ATLAS_DIRS.iter().any(|dir| s.starts_with(dir) && s[dir.len()..].starts_with('/'))) {
+            self.build_atlas(ctx)?;
+        }
+        Ok(())
+    }
+    /// Gets the sprite from the texture atlas, or as its own image if it isn't in it
+    pub fn get_sprite(&self, ctx: &mut Context, s: &'static str) -> Sprite {
+        match self.regions.get(s) {
+            Some(&(page, src)) => Sprite {
+                source: SpriteSource::Atlas(page),
+                image: self.atlas[page].clone(),
+                src,
+            },
+            None => Sprite {
+                source: SpriteSource::Image(s),
+                image: self.get_img(ctx, s).clone(),
+                src: Rect::one(),
+            },
+        }
+    }
+    /// Loads the images of the sprites so they don't have to be loaded when first drawn
+    ///
+    /// Tiles are drawn from the atlas if they're in it, so only those that aren't are loaded
+    /// on their own. Objects are always drawn from their own images.
+    pub fn preload<'a, T: IntoIterator<Item=&'a str>, O: IntoIterator<Item=&'a str>>(&self, ctx: &mut Context, tiles: T, objects: O) {
+        let tiles = tiles.into_iter().filter(|s| !self.regions.contains_key(*s));
+        for s in tiles.chain(objects) {
+            self.get_img(ctx, s);
+        }
     }
     /// Gets the `Image` to draw from the sprite
     #[inline]
     pub fn get_img(&self, ctx: &mut Context, s: &str) -> Ref<Image> {
         if !self.texes.borrow().contains_key(s) {
-            if let Ok(tex) = Image::new(ctx, &format!("/{}.png", s)) {
+            let tex = atlas::load(s).and_then(|img| Image::from_rgba8(ctx, img.width() as u16, img.height() as u16, &img.into_raw()));
+            if let Ok(tex) = tex {
                 self.texes.borrow_mut().insert(s.to_owned(), tex);
             } else if s != MISSING_TEXTURE {
                 let img = self.get_img(ctx, MISSING_TEXTURE).clone();
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::res;
+
+/// The directories of the resource packs whose files can be reloaded
+const DIRS: [&str; 6] = ["materials", "materials/autotile", "decorations", "pickups", "common", "sounds"];
+/// Kinds of files that can be reloaded
+const EXTENSIONS: [&str; 6] = ["mat", "light", "png", "wav", "ogg", "toml"];
+/// How long to wait between looking for changes in automatic mode
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Finds resource files that have changed by polling their modification times
+///
+/// Files are named by their path in the resource packs, like `materials/wall.png`.
+pub struct Watcher {
+    times: HashMap<String, SystemTime>,
+    /// Whether to look for changes by itself
+    pub auto: bool,
+    last_poll: Instant,
+}
+
+impl Watcher {
+    /// Remembers the modification times of the files as they are now
+    pub fn new() -> Self {
+        Watcher {
+            times: scan(),
+            auto: false,
+            last_poll: Instant::now(),
+        }
+    }
+    /// All the files being watched
+    pub fn files(&self) -> Vec<String> {
+        self.times.keys().cloned().collect()
+    }
+    /// The files that have been changed or added since last time
+    pub fn changes(&mut self) -> Vec<String> {
+        let times = scan();
+        let changed = times.iter()
+            .filter(|&(file, time)| self.times.get(file) != Some(time))
+            .map(|(file, _)| file.clone())
+            .collect();
+        self.times = times;
+        self.last_poll = Instant::now();
+        changed
+    }
+    /// The changes if in automatic mode and it's been long enough since last time
+    pub fn poll(&mut self) -> Vec<String> {
+        if self.auto && self.last_poll.elapsed() >= POLL_INTERVAL {
+            self.changes()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Default for Watcher {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn scan() -> HashMap<String, SystemTime> {
+    let mut times = HashMap::new();
+    add_time(&mut times, "editor.toml".to_owned());
+
+    for dir in &DIRS {
+        for name in res::list(dir) {
+            let watched = name.rsplit('.').next().is_some_and(|e| EXTENSIONS.contains(&e));
+            if watched {
+                add_time(&mut times, format!("{}/{}", dir, name));
+            }
+        }
+    }
+    times
+}
+
+#[inline]
+fn add_time(times: &mut HashMap<String, SystemTime>, file: String) {
+    if let Some(time) = res::modified(&file) {
+        times.insert(file, time);
+    }
+}
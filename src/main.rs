@@ -7,7 +7,7 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
-use std::env::args;
+use std::{env::args, process};
 
 use ggez::{
     ContextBuilder,
@@ -24,7 +24,6 @@ pub mod util {
     use std::{collections::HashSet, sync::Mutex};
     use lazy_static::lazy_static;
     use ggez::graphics::Color;
-    use ggez::{Context, input::keyboard::{self, KeyCode}};
     use serde::{Deserializer, Deserialize};
     use nalgebra::base::coordinates::XY;
     pub type Vector2 = nalgebra::Vector2<f32>;
@@ -35,6 +34,7 @@ pub mod util {
     pub const GREEN: Color = Color{r:0.1,g:0.7,b:0.1,a:1.};
     pub const RED: Color = Color{r:1.,g:0.,b:0.,a:1.};
     pub const BLUE: Color = Color{r:0.,g:0.,b:1.,a:1.};
+    pub const YELLOW: Color = Color{r:1.,g:1.,b:0.,a:1.};
 
     /// Makes a unit vector from a given direction angle
     pub fn angle_to_vec(angle: f32) -> Vector2 {
@@ -47,15 +47,6 @@ pub mod util {
         y.atan2(x)
     }
 
-    pub fn ver(ctx: &Context) -> f32 {
-        <f32>::from((keyboard::is_key_pressed(ctx, KeyCode::S) || keyboard::is_key_pressed(ctx, KeyCode::Down)) as i8 -
-            (keyboard::is_key_pressed(ctx, KeyCode::W) || keyboard::is_key_pressed(ctx, KeyCode::Up)) as i8)
-    }
-    pub fn hor(ctx: &Context) -> f32 {
-        <f32>::from((keyboard::is_key_pressed(ctx, KeyCode::D) || keyboard::is_key_pressed(ctx, KeyCode::Right)) as i8 -
-            (keyboard::is_key_pressed(ctx, KeyCode::A) || keyboard::is_key_pressed(ctx, KeyCode::Left)) as i8)
-    }
-
     lazy_static! {
         static ref STATIC_STRINGS: Mutex<HashSet<Sstr>> = Mutex::new(HashSet::new());
     }
@@ -96,11 +87,54 @@ pub mod util {
     }
 }
 
-use self::game::Master;
+use self::{game::{Master, validate}, io::res};
  
 fn main() {
     let mut args = args().skip(1);
-    let arg = args.next().unwrap_or_default();
+    let mut arg = String::new();
+    let mut validate = None;
+    let mut mods = Vec::new();
+
+    while let Some(a) = args.next() {
+        match &*a {
+            "--validate" => validate = Some(args.next().unwrap_or_else(|| {
+                eprintln!("Usage: topskud --validate <level or campaign>");
+                process::exit(2);
+            })),
+            "--mod" => mods.push(args.next().unwrap_or_else(|| {
+                eprintln!("Usage: topskud --mod <directory or zip>");
+                process::exit(2);
+            })),
+            _ => arg = a,
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    {
+        // Use the workspace's resources when running with cargo
+        if let Ok(manifest_dir) = ::std::env::var("CARGO_MANIFEST_DIR") {
+            res::set_base(::std::path::Path::new(&manifest_dir).join("resources"));
+        }
+    }
+    // Mods given first have the highest priority
+    if let Err(e) = res::set_mods(&mods) {
+        eprintln!("Couldn't load mods: {}", e);
+        process::exit(2);
+    }
+
+    if let Some(path) = validate {
+        // Check the assets without starting the game so it can be run in CI
+        let problems = validate::validate_path(&path);
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        if !problems.is_empty() {
+            eprintln!("{} problems found", problems.len());
+            process::exit(1);
+        }
+        println!("No problems found in {}", path);
+        return
+    }
 
     // Set window mode
     let window_mode = conf::WindowMode::default().dimensions(1152., 648.);
@@ -111,17 +145,6 @@ fn main() {
         .window_mode(window_mode)
         .build().unwrap();
 
-    #[cfg(debug_assertions)]
-    {
-        // Add the workspace directory to the filesystem when running with cargo
-        use ggez::filesystem;
-        if let Ok(manifest_dir) = ::std::env::var("CARGO_MANIFEST_DIR") {
-            let mut path = ::std::path::PathBuf::from(manifest_dir);
-            path.push("resources");
-            filesystem::mount(&mut ctx, &path, true);
-        }
-    }
-
     match Master::new(&mut ctx, &arg) {
         Err(e) => {
             eprintln!("Couldn't load game {}", e);
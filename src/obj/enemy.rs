@@ -48,15 +48,21 @@ pub struct Enemy {
     pub pl: Player,
     #[serde(skip)]
     pub behaviour: Chaser,
+    /// Index of the enemy in the level it was loaded from
+    #[serde(skip)]
+    pub id: usize,
 }
 
 pub const VISIBILITY: f32 = ::std::f32::consts::FRAC_PI_4;
+/// How far away enemies can see someone standing in pitch darkness
+pub const DARK_SIGHT: f32 = 96.;
 
 impl Enemy {
     pub fn new(obj: Object) -> Enemy {
         Enemy {
             pl: Player::new(obj),
             behaviour: Chaser::NoIntel,
+            id: 0,
         }
     }
     pub fn draw_visibility_cone(&self, ctx: &mut Context, length: f32) -> GameResult<()> {
@@ -119,10 +125,14 @@ impl Enemy {
         }
         Ok(())
     }
-    pub fn can_see(&self, p: Point2, palette: &Palette, grid: &Grid) -> bool {
+    /// Whether the point is seen, `light` being how lit up it is from 0 to 1
+    ///
+    /// The darker it is, the closer the point has to be to be seen.
+    pub fn can_see(&self, p: Point2, light: f32, palette: &Palette, grid: &Grid) -> bool {
         let dist = p-self.pl.obj.pos;
         let dir = angle_to_vec(self.pl.obj.rot);
+        let in_range = light >= 1. || dist.norm() <= DARK_SIGHT / (1. - light);
 
-        dir.angle(&dist) <= VISIBILITY && grid.ray_cast(palette, self.pl.obj.pos, dist, true).full()
+        in_range && dir.angle(&dist) <= VISIBILITY && grid.ray_cast(palette, self.pl.obj.pos, dist, true).full()
     }
 }